@@ -1,147 +1,653 @@
+use crate::config::CONFIG;
 use crate::errors::CommandError;
-use crate::utils::{EMPTY_VERSION, LATEST};
-use crate::versions::Versions;
+use crate::package_json::PackageJson;
+use crate::types::{CachedMetadata, PackageLock};
+use crate::utils::{self, GLOBAL_DIR, LATEST, TEMP_PREFIX};
+use crate::versions::{VersionRange, Versions};
 use lazy_static::lazy_static;
-use semver::{Comparator, Version};
-use std::collections::HashMap;
-use std::fs::{self as fs_sync, File};
-use std::io::{Read, Seek, SeekFrom};
+use log::{debug, warn};
+use semver::Version;
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::fs as fs_sync;
+use std::io::ErrorKind;
 use std::path::Path;
+use std::process;
 use std::str::FromStr;
 use std::string::String;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use tokio::fs;
 
 lazy_static! {
-    pub static ref CACHE_DIR: String = format!(
-        "{}/pie",
-        dirs::cache_dir()
-            .expect("Could not find cache directory")
-            .to_str()
-            .expect("Couldn't convert cache directory path to string")
-    );
-    pub static ref CACHED_VERSIONS: CachedVersions = Cache::get_cached_versions();
+    pub static ref CACHE_DIR: String = Cache::cache_dir();
+    /// The entries whose files were checked against their lock by this command, and whether
+    /// they matched.
+    static ref CHECKED_CONTENTS: Mutex<HashMap<String, bool>> = Mutex::new(HashMap::new());
+    /// The size in bytes the cache is trimmed to after each install.
+    pub static ref CACHE_MAX: Option<u64> = CONFIG
+        .cache_max
+        .filter(|max| *max > 0.0)
+        .map(|max| (max * GIGABYTE) as u64);
 }
 
-pub struct CachedVersion {
-    pub version: String,
-    pub is_latest: bool,
+const GIGABYTE: f64 = 1024.0 * 1024.0 * 1024.0;
+/// The file of a cache entry holding when it was last linked into a project, in seconds.
+const LAST_ACCESS: &str = ".last-access";
+/// The directory of the cached registry documents, one file per package.
+const METADATA_DIR: &str = ".metadata";
+const STALE_TEMP_AGE: Duration = Duration::from_secs(60 * 60);
+
+static LINK_STRATEGY: OnceLock<LinkStrategy> = OnceLock::new();
+
+/// How the cached packages are put into `node_modules`.
+#[derive(Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LinkStrategy {
+    /// A link to the cache entry, which takes no space.
+    #[default]
+    Symlink,
+    /// A copy of the cache entry, for the tools that don't follow symbolic links.
+    Copy,
+    /// The directories of the cache entry, holding hard links to its files. Real files like a
+    /// copy, which take no extra space.
+    Hardlink,
 }
 
-pub type CachedVersions = HashMap<String, CachedVersion>;
+impl FromStr for LinkStrategy {
+    type Err = ();
+
+    fn from_str(strategy: &str) -> Result<Self, Self::Err> {
+        match strategy {
+            "symlink" => Ok(Self::Symlink),
+            "copy" => Ok(Self::Copy),
+            "hardlink" => Ok(Self::Hardlink),
+            _ => Err(()),
+        }
+    }
+}
 
 pub struct Cache;
 impl Cache {
+    /// `PIE_CACHE_DIR` or the `cache-dir` of the `.pierc`, or else a `pie` directory in the
+    /// user's cache directory. It is created when missing, a first install then starts from
+    /// an empty cache.
+    fn cache_dir() -> String {
+        let dir = match &CONFIG.cache_dir {
+            Some(dir) => dir.trim_end_matches('/').to_string(),
+            None => format!(
+                "{}/pie",
+                dirs::cache_dir()
+                    .expect("Could not find cache directory")
+                    .to_str()
+                    .expect("Couldn't convert cache directory path to string")
+            ),
+        };
+
+        if let Err(e) = fs_sync::create_dir_all(&dir) {
+            warn!("Warning: failed to create the cache directory '{dir}' ({e})");
+        }
+
+        dir
+    }
+
     pub async fn exists(
         package_name: &String,
         version: Option<&String>,
-        sem_ver: Option<&Comparator>,
+        sem_ver: Option<&VersionRange>,
     ) -> Result<(bool, Option<String>), CommandError> {
         if let Some(version) = version {
             if version == LATEST {
-                let latest_version = Self::get_latest_version_in_cache(package_name);
+                let latest_version = Self::get_latest_version_in_cache(package_name).await?;
                 return Ok((latest_version.is_some(), latest_version));
             }
 
             return Ok((
-                Self::is_in_cache(package_name, &version),
+                Self::is_in_cache(package_name, version),
                 Some(version.to_string()),
             ));
         }
 
-        println!("{}", CACHE_DIR.to_string());
-        let mut cache_entries = fs::read_dir(CACHE_DIR.to_string())
+        let sem_ver = sem_ver.expect("Failed to get semver");
+        let best_match = Self::cached_versions(package_name)
+            .await?
+            .into_iter()
+            .filter(|version| sem_ver.matches(version))
+            .max();
+
+        match best_match {
+            Some(version) => Ok((true, Some(version.to_string()))),
+            None => Ok((false, None)),
+        }
+    }
+
+    /// The versions of a package fully installed in the cache. Only the entries of this
+    /// package are looked at, so the cost doesn't grow with the rest of the cache.
+    async fn cached_versions(package_name: &String) -> Result<Vec<Version>, CommandError> {
+        // scoped packages are cached under a directory named after their scope
+        let (scan_dir, prefix) = match package_name.split_once('/') {
+            Some((scope, _)) => (format!("{}/{}", *CACHE_DIR, scope), format!("{scope}/")),
+            None => (CACHE_DIR.to_string(), String::new()),
+        };
+
+        if !Path::new(&scan_dir).exists() {
+            return Ok(Vec::new());
+        }
+
+        let mut cache_entries = fs::read_dir(scan_dir)
             .await
             .map_err(CommandError::NoCacheDirectory)?;
-        let sem_ver = sem_ver.expect("Failed to get semver");
+        let mut versions = Vec::new();
 
         while let Some(cache_entry) = cache_entries
             .next_entry()
             .await
-            .map_err(CommandError::FailedDirectoryEntry)
-            .unwrap()
+            .map_err(CommandError::FailedDirectoryEntry)?
         {
-            let filename = cache_entry.file_name().to_string_lossy().to_string();
+            let filename = format!("{prefix}{}", cache_entry.file_name().to_string_lossy());
+            let (entry_name, entry_version) = Versions::parse_raw_package_details(filename);
 
-            if !filename.starts_with(package_name) {
+            if &entry_name != package_name {
                 continue;
             }
 
-            let (_, entry_version) = Versions::parse_raw_package_details(filename);
-            let version = &Version::from_str(entry_version.as_str()).unwrap_or(EMPTY_VERSION);
+            // a leftover directory such as an interrupted download isn't a cached version
+            let Ok(version) = Version::parse(&entry_version) else {
+                continue;
+            };
 
-            if sem_ver.matches(version) {
-                return Ok((true, Some(entry_version)));
+            if Self::is_in_cache(package_name, &entry_version) {
+                versions.push(version);
             }
         }
 
-        Ok((false, None))
+        Ok(versions)
     }
 
-    pub fn get_cached_versions() -> CachedVersions {
-        let dir = fs_sync::read_dir(CACHE_DIR.to_string()).expect("Failed to read cache directory");
-        let mut cached_versions = HashMap::new();
+    /// Lists every `name@version` entry of the cache, looking inside scope directories
+    /// so that scoped packages come out as `@scope/name@version`.
+    pub fn list_entries() -> Vec<String> {
+        let mut entries = Vec::new();
 
-        for entry in dir {
-            let entry = entry.expect("Failed to get cache entry");
+        // a cache directory that couldn't be created, or was just cleaned, is an empty cache
+        let Ok(dir) = fs_sync::read_dir(CACHE_DIR.to_string()) else {
+            return entries;
+        };
+
+        for entry in dir.filter_map(Result::ok) {
             let filename = entry.file_name().to_string_lossy().to_string();
 
-            let mut lock = File::open(format!("{}/{}/package/pie-lock.json", *CACHE_DIR, filename))
-                .expect("Failed to open lock file");
+            // extractions in progress or left behind by a crash, and the registry documents
+            if filename.starts_with('.') {
+                continue;
+            }
 
-            let start_byte = 12;
-            let end_byte = 15;
+            if !filename.starts_with('@') {
+                entries.push(filename);
+                continue;
+            }
 
-            let mut buf = vec![0; end_byte - start_byte + 1];
-            lock.seek(SeekFrom::Start(start_byte as u64)).unwrap();
-            lock.read_exact(&mut buf).unwrap();
+            let scope_dir = fs_sync::read_dir(entry.path()).into_iter().flatten();
+            for scoped_entry in scope_dir.filter_map(Result::ok) {
+                let scoped_filename = scoped_entry.file_name().to_string_lossy().to_string();
+                if !scoped_filename.starts_with(TEMP_PREFIX) {
+                    entries.push(format!("{}/{}", filename, scoped_filename));
+                }
+            }
+        }
+
+        entries
+    }
 
-            let is_latest = String::from_utf8(buf).unwrap() == "true";
+    /// The extraction directories of the cache, next to the entries they become.
+    fn temp_dirs() -> Vec<fs_sync::DirEntry> {
+        let Ok(dir) = fs_sync::read_dir(CACHE_DIR.as_str()) else {
+            return Vec::new();
+        };
 
-            let (name, version) = Versions::parse_raw_package_details(filename);
-            cached_versions.insert(name, CachedVersion { version, is_latest });
+        let mut dirs = Vec::new();
+        for entry in dir.filter_map(Result::ok) {
+            let filename = entry.file_name().to_string_lossy().to_string();
+            if filename.starts_with('@') {
+                let scope_dir = fs_sync::read_dir(entry.path()).into_iter().flatten();
+                dirs.extend(scope_dir.filter_map(Result::ok));
+            } else {
+                dirs.push(entry);
+            }
         }
 
-        cached_versions
+        dirs.retain(|entry| entry.file_name().to_string_lossy().starts_with(TEMP_PREFIX));
+        dirs
+    }
+
+    /// Removes the extraction directories and partial downloads older than an hour, which a
+    /// crash left behind. Younger ones may belong to an install still running, or be resumed.
+    pub fn remove_stale_temp_dirs() {
+        for entry in Self::temp_dirs() {
+            let is_stale = entry
+                .metadata()
+                .and_then(|metadata| metadata.modified())
+                .is_ok_and(|modified| {
+                    modified
+                        .elapsed()
+                        .is_ok_and(|elapsed| elapsed > STALE_TEMP_AGE)
+                });
+
+            if is_stale {
+                debug!(
+                    "Removing stale extraction directory '{}'",
+                    entry.path().display()
+                );
+                let _ = match entry.file_type().is_ok_and(|file_type| file_type.is_dir()) {
+                    true => fs_sync::remove_dir_all(entry.path()),
+                    false => fs_sync::remove_file(entry.path()),
+                };
+            }
+        }
     }
 
-    pub fn get_latest_version_in_cache(package_name: &String) -> Option<String> {
-        let versions = CACHED_VERSIONS.get(package_name);
-        match versions {
-            Some(v) if v.is_latest => Some(v.version.clone()),
-            _ => None,
+    /// Removes the extraction directories of this process, when it is interrupted. Its partial
+    /// downloads are kept, to be resumed by the next install.
+    pub fn remove_own_temp_dirs() {
+        let own_prefix = format!("{TEMP_PREFIX}{}-", process::id());
+        for entry in Self::temp_dirs() {
+            let is_dir = entry.file_type().is_ok_and(|file_type| file_type.is_dir());
+            if is_dir && entry.file_name().to_string_lossy().starts_with(&own_prefix) {
+                let _ = fs_sync::remove_dir_all(entry.path());
+            }
         }
     }
 
+    /// The highest cached version that was the `latest` one when it was installed.
+    pub async fn get_latest_version_in_cache(
+        package_name: &String,
+    ) -> Result<Option<String>, CommandError> {
+        let mut versions = Self::cached_versions(package_name).await?;
+        versions.sort();
+
+        let latest = versions.into_iter().rev().find(|version| {
+            Self::read_lock(&Versions::stringify(package_name, &version.to_string()))
+                .is_ok_and(|lock| lock.is_latest)
+        });
+        Ok(latest.map(|version| version.to_string()))
+    }
+
     pub fn is_in_cache(package: &String, version: &String) -> bool {
-        let cached_version = CACHED_VERSIONS.get(package);
-        match cached_version {
-            Some(v) if &v.version == version => true,
-            _ => false,
+        Self::is_complete(&Versions::stringify(package, version))
+    }
+
+    /// Whether a package was fully installed in the cache, which is when its lock is written.
+    fn is_complete(package: &String) -> bool {
+        Path::new(&format!("{}/{}/package/pie-lock.json", *CACHE_DIR, package)).exists()
+    }
+
+    /// The dependencies listed by the lock of a cached package that aren't in the cache,
+    /// because they were evicted or never fully installed.
+    pub fn missing_dependencies(package: &String) -> Vec<String> {
+        Self::read_lock(package)
+            .map(|lock| lock.dependencies)
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|dependency| !Self::is_complete(dependency))
+            .collect()
+    }
+
+    /// The hash of the files of a cache entry, without its lock.
+    pub fn contents_hash(package: &String) -> Option<String> {
+        let package_dir = format!("{}/{}/package", *CACHE_DIR, package);
+        utils::hash_dir(Path::new(&package_dir), &["pie-lock.json"]).ok()
+    }
+
+    /// Whether the files of a cache entry are still the ones it was locked with. An entry
+    /// locked before their hash was recorded can't tell, and is trusted.
+    pub fn is_unaltered(package: &String) -> bool {
+        if let Some(unaltered) = utils::lock(&CHECKED_CONTENTS).get(package) {
+            return *unaltered;
         }
+
+        let unaltered = match Self::read_lock(package).ok().and_then(|lock| lock.contents) {
+            Some(contents) => Self::contents_hash(package) == Some(contents),
+            None => true,
+        };
+        utils::lock(&CHECKED_CONTENTS).insert(package.to_string(), unaltered);
+        unaltered
+    }
+
+    /// Removes the lock of a cache entry, which makes it incomplete, so that the next
+    /// install downloads it again.
+    pub fn invalidate(package: &String) {
+        let _ = fs_sync::remove_file(format!("{}/{}/package/pie-lock.json", *CACHE_DIR, package));
+        utils::lock(&CHECKED_CONTENTS).remove(package);
     }
 
-    pub fn load_cached_version(package: String) {
+    pub fn read_lock(package: &String) -> Result<PackageLock, CommandError> {
         let raw =
             fs_sync::read_to_string(format!("{}/{}/package/pie-lock.json", *CACHE_DIR, package))
-                .expect("Failed to read lock file");
-        let lock = serde_json::from_str::<PackageLock>(raw.as_str()).unwrap();
+                .map_err(CommandError::FailedToReadFile)?;
+        serde_json::from_str::<PackageLock>(raw.as_str()).map_err(CommandError::ParsingFailed)
+    }
+
+    pub fn write_lock(package: &String, lock: &PackageLock) -> Result<(), CommandError> {
+        let path = format!("{}/{}/package", *CACHE_DIR, package);
+        fs_sync::create_dir_all(&path).map_err(CommandError::FailedToCreateDir)?;
+        let raw =
+            serde_json::to_string(lock).map_err(CommandError::FailedToSerializePackageLock)?;
+        fs_sync::write(format!("{path}/pie-lock.json"), raw)
+            .map_err(CommandError::FailedToWriteFile)
+    }
+
+    /// The file of the cached registry document of a route, its `/` escaped like in the
+    /// registry URLs of the scoped packages.
+    fn metadata_path(route: &str) -> String {
+        format!(
+            "{}/{}/{}.json",
+            *CACHE_DIR,
+            METADATA_DIR,
+            route.replace('/', "%2f")
+        )
+    }
+
+    pub fn read_metadata(route: &str) -> Option<CachedMetadata> {
+        let raw = fs_sync::read_to_string(Self::metadata_path(route)).ok()?;
+        serde_json::from_str(&raw).ok()
+    }
+
+    /// Stores the registry document of a route. It is only an optimization, so failing to
+    /// write it doesn't fail the command.
+    pub fn write_metadata(route: &str, metadata: &CachedMetadata) {
+        let path = Self::metadata_path(route);
+        let Ok(raw) = serde_json::to_string(metadata) else {
+            return;
+        };
+
+        // renamed once written, so that a concurrent read never gets half a document
+        let temp_path = format!(
+            "{}/{}/{TEMP_PREFIX}{}-{}",
+            *CACHE_DIR,
+            METADATA_DIR,
+            process::id(),
+            route.replace('/', "%2f")
+        );
+        let result = fs_sync::create_dir_all(format!("{}/{}", *CACHE_DIR, METADATA_DIR))
+            .and_then(|_| fs_sync::write(&temp_path, raw))
+            .and_then(|_| fs_sync::rename(&temp_path, &path));
+
+        if let Err(e) = result {
+            debug!("Failed to cache the registry document of '{route}' ({e})");
+            let _ = fs_sync::remove_file(&temp_path);
+        }
+    }
+
+    /// Returns the `name@version` of every package linked into `./node_modules`,
+    /// found by following each link back to its cache entry.
+    pub fn linked_packages() -> Result<Vec<String>, CommandError> {
+        Self::linked_packages_in(Path::new("./node_modules"))
+    }
+
+    fn linked_packages_in(node_modules: &Path) -> Result<Vec<String>, CommandError> {
+        let mut links = Vec::new();
+
+        if !node_modules.exists() {
+            return Ok(links);
+        }
+
+        for entry in fs_sync::read_dir(node_modules).map_err(CommandError::FailedToReadFile)? {
+            let entry = entry.map_err(CommandError::FailedDirectoryEntry)?;
+            let filename = entry.file_name().to_string_lossy().to_string();
+
+            if filename.starts_with('@') {
+                for scoped_entry in
+                    fs_sync::read_dir(entry.path()).map_err(CommandError::FailedToReadFile)?
+                {
+                    let scoped_entry = scoped_entry.map_err(CommandError::FailedDirectoryEntry)?;
+                    links.extend(Self::link_target(&scoped_entry.path()));
+                }
+                continue;
+            }
+
+            links.extend(Self::link_target(&entry.path()));
+        }
+
+        Ok(links)
+    }
+
+    /// Records that a cache entry was just used, for the cache eviction to keep it longer.
+    fn touch(package: &String) {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs());
+        let _ = fs_sync::write(
+            format!("{}/{}/{}", *CACHE_DIR, package, LAST_ACCESS),
+            now.to_string(),
+        );
+    }
+
+    /// When a cache entry was last used, entries cached before it was recorded fall back
+    /// on their modification time.
+    fn last_access(package: &String) -> u64 {
+        let entry = format!("{}/{}", *CACHE_DIR, package);
+        fs_sync::read_to_string(format!("{entry}/{LAST_ACCESS}"))
+            .ok()
+            .and_then(|raw| raw.trim().parse().ok())
+            .or_else(|| {
+                let modified = fs_sync::metadata(&entry).ok()?.modified().ok()?;
+                Some(modified.duration_since(UNIX_EPOCH).ok()?.as_secs())
+            })
+            .unwrap_or(0)
+    }
+
+    /// Evicts the least recently used entries until the cache fits in `max_size` bytes.
+    /// The entries linked into the current project or the global directory are always kept.
+    /// Returns the evicted entries, along with the number of bytes reclaimed.
+    pub fn gc(max_size: u64) -> Result<(Vec<String>, u64), CommandError> {
+        if !Path::new(CACHE_DIR.as_str()).exists() {
+            return Ok((Vec::new(), 0));
+        }
+
+        let mut linked = Self::linked_packages()?;
+        linked.extend(Self::linked_packages_in(
+            &Path::new(GLOBAL_DIR.as_str()).join("node_modules"),
+        )?);
+
+        // a linked package needs every dependency of its lock
+        let mut protected = HashSet::new();
+        for package in linked {
+            if let Ok(lock) = Self::read_lock(&package) {
+                protected.extend(lock.dependencies);
+            }
+            protected.insert(package);
+        }
+
+        let mut entries = Self::list_entries()
+            .into_iter()
+            .map(|entry| {
+                let size = utils::dir_size(Path::new(&format!("{}/{}", *CACHE_DIR, entry)));
+                (Self::last_access(&entry), size, entry)
+            })
+            .collect::<Vec<_>>();
+        entries.sort();
+
+        let mut total = entries.iter().map(|(_, size, _)| size).sum::<u64>();
+        let mut evicted = Vec::new();
+        let mut reclaimed = 0;
+
+        for (_, size, entry) in entries {
+            if total <= max_size {
+                break;
+            }
+            if protected.contains(&entry) {
+                continue;
+            }
+
+            fs_sync::remove_dir_all(format!("{}/{}", *CACHE_DIR, entry))
+                .map_err(CommandError::FailedToRemoveFile)?;
+            if let Some((scope, _)) = entry.split_once('/') {
+                // only removed once the scope has no cached package left
+                let _ = fs_sync::remove_dir(format!("{}/{}", *CACHE_DIR, scope));
+            }
+            total -= size;
+            reclaimed += size;
+            evicted.push(entry);
+        }
+
+        Ok((evicted, reclaimed))
+    }
+
+    /// The `--copy` flag of `install` takes precedence over the configured strategy.
+    pub fn set_link_strategy(strategy: LinkStrategy) {
+        let _ = LINK_STRATEGY.set(strategy);
+    }
+
+    fn link_strategy() -> LinkStrategy {
+        *LINK_STRATEGY.get_or_init(|| CONFIG.link_strategy.unwrap_or_default())
+    }
+
+    /// The cache entry a package of `node_modules` comes from, a copied one is known by its
+    /// `package.json`.
+    fn link_target(link: &Path) -> Option<String> {
+        let Ok(target) = fs_sync::read_link(link) else {
+            let package_json = PackageJson::read_from(&link.join("package.json")).ok()?;
+            let package = Versions::stringify(&package_json.name?, &package_json.version?);
+            return Self::is_complete(&package).then_some(package);
+        };
+        let entry = target.parent()?.strip_prefix(CACHE_DIR.as_str()).ok()?;
+        Some(entry.to_string_lossy().to_string())
+    }
+
+    /// Links the executables of a package into `bin_dir` and makes them executable.
+    pub fn link_bins(package: &String, bin_dir: &Path) -> Result<Vec<String>, CommandError> {
+        let lock = Self::read_lock(package)?;
+        if lock.bin.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        fs_sync::create_dir_all(bin_dir).map_err(CommandError::FailedToCreateDir)?;
+
+        let mut commands = Vec::new();
+        for (command, path) in lock.bin {
+            let target = Path::new(CACHE_DIR.as_str())
+                .join(package)
+                .join("package")
+                .join(path.trim_start_matches("./"));
+            let link = bin_dir.join(&command);
+
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::PermissionsExt;
+                if let Ok(metadata) = fs_sync::metadata(&target) {
+                    let mut permissions = metadata.permissions();
+                    permissions.set_mode(permissions.mode() | 0o111);
+                    fs_sync::set_permissions(&target, permissions)
+                        .map_err(CommandError::FailedToWriteFile)?;
+                }
+            }
+
+            // a previously installed version of the command is replaced
+            if fs_sync::symlink_metadata(&link).is_ok() {
+                symlink::remove_symlink_file(&link).map_err(CommandError::FailedToRemoveFile)?;
+            }
+            symlink::symlink_file(&target, &link).map_err(CommandError::FailedToCreateFile)?;
+            commands.push(command);
+        }
+
+        Ok(commands)
+    }
+
+    /// Removes a package from `node_modules`, along with its executables, when it is the
+    /// version linked there.
+    pub fn unlink_version(package: &String) -> Result<(), CommandError> {
+        let (name, _) = Versions::parse_raw_package_details(package.to_string());
+        let destination = format!("./node_modules/{name}");
+        let destination = Path::new(&destination);
+        if Self::link_target(destination).as_ref() != Some(package) {
+            return Ok(());
+        }
+
+        let bin_dir = Path::new("./node_modules/.bin");
+        let package_dir = Path::new(CACHE_DIR.as_str()).join(package);
+        let commands = Self::read_lock(package)
+            .map(|lock| lock.bin)
+            .unwrap_or_default();
+        for command in commands.keys() {
+            let link = bin_dir.join(command);
+            let points_here =
+                fs_sync::read_link(&link).is_ok_and(|target| target.starts_with(&package_dir));
+            if points_here {
+                symlink::remove_symlink_file(&link).map_err(CommandError::FailedToRemoveFile)?;
+            }
+        }
+
+        utils::unlink_dir(destination).map_err(CommandError::FailedToRemoveFile)
+    }
+
+    pub fn load_cached_version(package: String) -> Result<(), CommandError> {
+        let lock = Self::read_lock(&package)?;
 
         let mut dependencies = lock.dependencies;
         dependencies.push(package);
 
         for d in dependencies {
+            // a link to a missing entry would be dangling
+            if !Self::is_complete(&d) {
+                warn!(
+                    "Warning: '{}' is missing from the cache, it wasn't linked",
+                    d
+                );
+                continue;
+            }
+
+            if !Self::is_unaltered(&d) {
+                warn!(
+                    "Warning: the cached files of '{}' changed since it was installed, it wasn't linked",
+                    d
+                );
+                continue;
+            }
+
+            Self::touch(&d);
             let (name, _) = Versions::parse_raw_package_details(d.to_string());
 
-            let link = symlink::symlink_dir(
-                format!("{}/{}/package", *CACHE_DIR, d),
-                format!("./node_modules/{}", name),
-            );
+            // scoped packages live in a `node_modules/@scope` directory shared by the whole scope
+            if let Some((scope, _)) = name.split_once('/') {
+                fs_sync::create_dir_all(format!("./node_modules/{}", scope))
+                    .map_err(|e| CommandError::FailedToLink(d.to_string(), e))?;
+            }
+
+            let target = format!("{}/{}/package", *CACHE_DIR, d);
+            let target = Path::new(&target);
+            let destination = format!("./node_modules/{}", name);
+            let destination = Path::new(&destination);
+
+            // another version linked there is replaced
+            match Self::link_target(destination) {
+                Some(linked) if linked == d => {}
+                Some(linked) => Self::unlink_version(&linked)?,
+                None if fs_sync::symlink_metadata(destination).is_ok() => {
+                    utils::unlink_dir(destination)
+                        .map_err(|e| CommandError::FailedToLink(d.to_string(), e))?
+                }
+                None => {}
+            }
+
+            let link = match Self::link_strategy() {
+                LinkStrategy::Symlink => utils::link_dir(target, destination),
+                LinkStrategy::Copy => utils::copy_dir(target, destination),
+                LinkStrategy::Hardlink => utils::hardlink_dir(target, destination),
+            };
 
             match link {
-                Ok(_) => continue,
-                Err(err) if err.kind() == ErrorKind::AlreadyExists => continue,
-                Err(e) => panic!("Failed to create symlink: {}", e),
+                Ok(_) => {}
+                Err(err)
+                    if err.kind() == ErrorKind::AlreadyExists
+                        && Self::link_target(destination).as_ref() == Some(&d) => {}
+                Err(e) => return Err(CommandError::FailedToLink(d.to_string(), e)),
+            }
+
+            if let Err(e) = Self::link_bins(&d, Path::new("./node_modules/.bin")) {
+                warn!("Failed to link the executables of '{}' ({e})", d);
             }
         }
+
+        Ok(())
     }
 }
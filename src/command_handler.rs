@@ -1,8 +1,36 @@
+use crate::cache::Cache;
+use crate::config::CONFIG;
 use crate::errors::ParseError::CommandNotFound;
-use crate::errors::{CommandError, ParseError};
+use crate::errors::{CommandError, ParseError, PieError};
+use crate::handlers::audit::AuditHandler;
+use crate::handlers::cache::CacheHandler;
+use crate::handlers::check::CheckHandler;
+use crate::handlers::dedupe::DedupeHandler;
+use crate::handlers::exec::ExecHandler;
+use crate::handlers::graph::GraphHandler;
+use crate::handlers::info::InfoHandler;
+use crate::handlers::init::InitHandler;
 use crate::handlers::install::InstallHandler;
+use crate::handlers::list::ListHandler;
+use crate::handlers::outdated::OutdatedHandler;
+use crate::handlers::search::SearchHandler;
+use crate::handlers::uninstall::UninstallHandler;
+use crate::handlers::update::UpdateHandler;
+use crate::http::HttpRequest;
+use crate::report::Reporter;
 use async_trait::async_trait;
-use std::env::Args;
+use log::{warn, LevelFilter};
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::process;
+use tokio::signal;
+
+/// The exit code of a process stopped by SIGINT, by convention.
+const INTERRUPTED_EXIT_CODE: i32 = 130;
+
+/// The arguments left for a command once the global flags are taken out.
+pub type Args = std::vec::IntoIter<String>;
 
 #[async_trait]
 pub trait CommandHandler {
@@ -10,27 +38,158 @@ pub trait CommandHandler {
     async fn execute(&self) -> Result<(), CommandError>;
 }
 
-pub async fn handle_args(mut args: Args) -> Result<(), ParseError> {
-    args.next();
+/// Prints log records as is, `--verbose` adds the per-task detail and `--quiet` only keeps errors.
+/// A JSON report owns the standard output, the logs are then printed to the error output.
+fn init_logger(level: LevelFilter) {
+    let target = match Reporter::is_json() {
+        true => env_logger::Target::Stderr,
+        false => env_logger::Target::Stdout,
+    };
+
+    env_logger::Builder::new()
+        .filter_level(level)
+        .format(|buf, record| writeln!(buf, "{}", record.args()))
+        .target(target)
+        .init();
+}
 
-    let command = match args.next() {
-        Some(c) => c,
-        None => {
-            println!("Please provide a command.");
-            return Ok(());
+/// Stops the command on Ctrl-C, removing the extractions it left halfway. A second Ctrl-C
+/// while cleaning up is ignored, as the first one is handled already.
+fn handle_interrupts() {
+    tokio::spawn(async {
+        if signal::ctrl_c().await.is_err() {
+            return;
         }
-    };
+
+        warn!("Interrupted, removing the partial extractions...");
+        Cache::remove_own_temp_dirs();
+        process::exit(INTERRUPTED_EXIT_CODE);
+    });
+}
+
+/// Moves into the directory of `--prefix`, creating it when missing. Every path of pie is
+/// relative to the current directory, so the command then runs as if started from there.
+fn enter_prefix(prefix: &str) -> Result<(), CommandError> {
+    fs::create_dir_all(prefix).map_err(CommandError::FailedToCreateDir)?;
+    env::set_current_dir(prefix).map_err(CommandError::FailedToChangeDir)
+}
+
+/// Whether `arg` is the command run by `exec`, the first argument after it that is neither
+/// an option nor the package of `--package`.
+fn starts_exec_command(remaining: &[String], arg: &str) -> bool {
+    remaining
+        .first()
+        .is_some_and(|command| command.eq_ignore_ascii_case("exec"))
+        && !arg.starts_with('-')
+        && !remaining
+            .last()
+            .is_some_and(|previous| previous == "-p" || previous == "--package")
+}
+
+pub async fn handle_args(args: impl Iterator<Item = String>) -> Result<(), PieError> {
+    let mut level = None;
+    let mut prefix = None;
+    let mut remaining = Vec::new();
+    let mut args = args.skip(1);
+
+    // global flags can be given anywhere, before or after the command, but not after a `--`
+    // nor after the command run by `exec`
+    while let Some(arg) = args.next() {
+        let (flag, flag_level) = match arg.as_str() {
+            "--" => {
+                remaining.push(arg);
+                remaining.extend(args.by_ref());
+                break;
+            }
+            "--verbose" | "-v" => ("--verbose", LevelFilter::Debug),
+            "--quiet" | "-q" => ("--quiet", LevelFilter::Error),
+            "--json" => {
+                Reporter::set_json(true);
+                continue;
+            }
+            "--registry" => {
+                let registry = args
+                    .next()
+                    .ok_or(ParseError::MissingArgument(String::from("registry")))
+                    .map_err(PieError::Parse)?;
+                HttpRequest::set_registry(registry).map_err(PieError::Parse)?;
+                continue;
+            }
+            "--prefix" => {
+                let dir = args
+                    .next()
+                    .ok_or(ParseError::MissingArgument(String::from("prefix")))
+                    .map_err(PieError::Parse)?;
+                prefix = Some(dir);
+                continue;
+            }
+            _ if arg.starts_with("--prefix=") => {
+                prefix = Some(arg["--prefix=".len()..].to_string());
+                continue;
+            }
+            _ if arg.starts_with("--registry=") => {
+                let registry = arg["--registry=".len()..].to_string();
+                HttpRequest::set_registry(registry).map_err(PieError::Parse)?;
+                continue;
+            }
+            _ => {
+                let runs_command = starts_exec_command(&remaining, &arg);
+                remaining.push(arg);
+                // the arguments of the command run by `exec` are its own, global flags included
+                if runs_command {
+                    remaining.extend(args.by_ref());
+                    break;
+                }
+                continue;
+            }
+        };
+
+        match level {
+            Some((other, other_level)) if other_level != flag_level => {
+                return Err(PieError::Parse(ParseError::ConflictingArguments(
+                    String::from(other),
+                    String::from(flag),
+                )));
+            }
+            _ => level = Some((flag, flag_level)),
+        }
+    }
+
+    init_logger(level.map_or(LevelFilter::Info, |(_, level)| level));
+    // before anything reads the configuration, so that the `.pierc` of the prefix is used
+    if let Some(prefix) = &prefix {
+        enter_prefix(prefix).map_err(PieError::Command)?;
+    }
+    // loaded here rather than by whatever reads it first, so that the warnings of a broken
+    // `.pierc` are always logged
+    lazy_static::initialize(&CONFIG);
+    handle_interrupts();
+    Cache::remove_stale_temp_dirs();
+    let mut args = remaining.into_iter();
+
+    let command = args
+        .next()
+        .ok_or(ParseError::MissingArgument(String::from("command")))
+        .map_err(PieError::Parse)?;
 
     let mut command_handler: Box<dyn CommandHandler> = match command.to_lowercase().as_str() {
         "install" => Box::<InstallHandler>::default(),
-        _ => return Err(CommandNotFound(command.to_string())),
+        "uninstall" => Box::<UninstallHandler>::default(),
+        "list" | "ls" => Box::<ListHandler>::default(),
+        "info" => Box::<InfoHandler>::default(),
+        "search" => Box::<SearchHandler>::default(),
+        "cache" => Box::<CacheHandler>::default(),
+        "outdated" => Box::<OutdatedHandler>::default(),
+        "check" => Box::<CheckHandler>::default(),
+        "audit" => Box::<AuditHandler>::default(),
+        "graph" => Box::<GraphHandler>::default(),
+        "exec" => Box::<ExecHandler>::default(),
+        "update" => Box::<UpdateHandler>::default(),
+        "dedupe" => Box::<DedupeHandler>::default(),
+        "init" => Box::<InitHandler>::default(),
+        _ => return Err(PieError::Parse(CommandNotFound(command.to_string()))),
     };
 
-    command_handler.parse(&mut args)?;
-    let command_result = command_handler.execute().await;
-
-    if let Err(e) = command_result {
-        println!("Command error : {e}")
-    }
-    Ok(())
+    command_handler.parse(&mut args).map_err(PieError::Parse)?;
+    command_handler.execute().await.map_err(PieError::Command)
 }
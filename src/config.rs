@@ -0,0 +1,106 @@
+use crate::cache::LinkStrategy;
+use crate::types::Severity;
+use lazy_static::lazy_static;
+use log::warn;
+use serde::Deserialize;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+pub const PIERC: &str = ".pierc";
+
+lazy_static! {
+    /// The settings of pie, read once from the environment and the `.pierc` files. They are
+    /// global rather than passed to the handlers, as most are read by the other globals,
+    /// e.g. `CACHE_DIR` or the HTTP timeout, which handlers can't hand them to.
+    pub static ref CONFIG: Config = Config::load();
+}
+
+/// The settings of pie. Each one comes from its `PIE_*` environment variable, or else from
+/// the project `.pierc`, or else from the one in the home directory. A setting that is still
+/// unset gets its default where it is used.
+#[derive(Default, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Config {
+    pub registry: Option<String>,
+    pub max_concurrency: Option<usize>,
+    pub extraction_workers: Option<usize>,
+    /// In seconds.
+    pub timeout: Option<u64>,
+    /// In seconds.
+    pub install_timeout: Option<u64>,
+    pub http_retries: Option<u32>,
+    pub cache_dir: Option<String>,
+    /// In gigabytes.
+    pub cache_max: Option<f64>,
+    pub link_strategy: Option<LinkStrategy>,
+    /// Whether the `postinstall` scripts of the installed packages are run.
+    pub run_scripts: Option<bool>,
+    /// The least severe vulnerability that fails `pie audit`.
+    pub audit_level: Option<Severity>,
+}
+
+impl Config {
+    fn load() -> Self {
+        let home_pierc = dirs::home_dir().map(|home| home.join(PIERC));
+        let project_pierc = PathBuf::from(PIERC);
+
+        let from_files = home_pierc
+            .iter()
+            .chain([&project_pierc])
+            .map(|path| Self::read(path))
+            .fold(Self::default(), |config, overrides| overrides.or(config));
+
+        Self::from_env().or(from_files)
+    }
+
+    /// Reads a `.pierc`, a file that can't be read as one is ignored with a warning.
+    fn read(path: &Path) -> Self {
+        let Ok(raw) = fs::read_to_string(path) else {
+            return Self::default();
+        };
+
+        toml::from_str(&raw).unwrap_or_else(|e| {
+            warn!("Warning: ignoring '{}' ({e})", path.display());
+            Self::default()
+        })
+    }
+
+    fn from_env() -> Self {
+        Self {
+            registry: env::var("PIE_REGISTRY").ok(),
+            max_concurrency: Self::parse_env("PIE_MAX_CONCURRENCY"),
+            extraction_workers: Self::parse_env("PIE_EXTRACTION_WORKERS"),
+            timeout: Self::parse_env("PIE_TIMEOUT"),
+            install_timeout: Self::parse_env("PIE_INSTALL_TIMEOUT"),
+            http_retries: Self::parse_env("PIE_HTTP_RETRIES"),
+            cache_dir: env::var("PIE_CACHE_DIR").ok(),
+            cache_max: Self::parse_env("PIE_CACHE_MAX"),
+            link_strategy: Self::parse_env("PIE_LINK_STRATEGY"),
+            run_scripts: Self::parse_env("PIE_RUN_SCRIPTS"),
+            audit_level: Self::parse_env("PIE_AUDIT_LEVEL"),
+        }
+    }
+
+    fn parse_env<T: FromStr>(name: &str) -> Option<T> {
+        env::var(name).ok()?.parse().ok()
+    }
+
+    /// Keeps every setting that is set, taking the others from `fallback`.
+    fn or(self, fallback: Self) -> Self {
+        Self {
+            registry: self.registry.or(fallback.registry),
+            max_concurrency: self.max_concurrency.or(fallback.max_concurrency),
+            extraction_workers: self.extraction_workers.or(fallback.extraction_workers),
+            timeout: self.timeout.or(fallback.timeout),
+            install_timeout: self.install_timeout.or(fallback.install_timeout),
+            http_retries: self.http_retries.or(fallback.http_retries),
+            cache_dir: self.cache_dir.or(fallback.cache_dir),
+            cache_max: self.cache_max.or(fallback.cache_max),
+            link_strategy: self.link_strategy.or(fallback.link_strategy),
+            run_scripts: self.run_scripts.or(fallback.run_scripts),
+            audit_level: self.audit_level.or(fallback.audit_level),
+        }
+    }
+}
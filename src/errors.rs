@@ -9,24 +9,56 @@ pub enum ParseError {
     MissingArgument(String),
     #[error("invalid version notation ({0})")]
     InvalidVersionNotation(semver::Error),
+    #[error("invalid argument: '{0}'")]
+    InvalidArgument(String),
+    #[error("'{0}' can't be combined with '{1}'")]
+    ConflictingArguments(String, String),
+    #[error("'{0}' is not a valid package name, {1}")]
+    InvalidPackageName(String, String),
 }
 
 #[derive(Error, Debug)]
 pub enum CommandError {
     #[error("failed to execute http request ({0})")]
     HTTPFailed(reqwest::Error),
+    #[error("request to '{0}' timed out, the timeout can be raised with PIE_TIMEOUT")]
+    Timeout(String),
+    #[error("the registry refused to serve '{0}' (status {1}), check your auth token")]
+    Unauthorized(String, u16),
     #[error("failed to parse http data to struct via json ({0})")]
     ParsingFailed(serde_json::Error),
     #[error("failed to get http response text ({0})")]
     FailedResponseText(reqwest::Error),
     #[error("failed to get http response bytes ({0})")]
     FailedResponseBytes(reqwest::Error),
-    #[error("the package version you provided was invalid or does not exist")]
-    InvalidVersion,
+    #[error("'{0}' was not found in the registry")]
+    PackageNotFound(String),
+    #[error("the registry doesn't support searching")]
+    SearchUnsupported,
+    #[error("the registry doesn't support security audits")]
+    AuditUnsupported,
+    #[error("{0} vulnerability(ies) of severity '{1}' or higher found")]
+    VulnerabilitiesFound(usize, String),
+    #[error("no published version matches the range '{0}'")]
+    InvalidVersion(String),
+    #[error("failed to link '{0}' into node_modules ({1})")]
+    FailedToLink(String, Error),
+    #[error("the registry has no dist-tag named '{0}'")]
+    UnknownDistTag(String),
+    #[error("the registry has no tarball for '{0}@{1}'")]
+    MissingTarball(String, String),
+    #[error("integrity check failed for '{0}', the downloaded tarball doesn't match the registry checksum")]
+    IntegrityMismatch(String),
     #[error("failed to extract tar file ({0})")]
     ExtractionFailed(Error),
+    #[error("tarball entry '{0}' would be extracted outside of its package directory")]
+    UnsafeTarballEntry(String),
+    #[error("'{0}' is not in the cache, it can't be installed offline")]
+    NotCached(String),
     #[error("could not find cache directory ({0})")]
     NoCacheDirectory(Error),
+    #[error("no cache size limit is set, set PIE_CACHE_MAX to a size in gigabytes")]
+    NoCacheLimit,
     #[error("failed to get directory entry ({0})")]
     FailedDirectoryEntry(Error),
     #[error("failed to create file ({0})")]
@@ -37,4 +69,91 @@ pub enum CommandError {
     FailedToSerializePackageLock(serde_json::Error),
     #[error("failed to write file ({0})")]
     FailedToWriteFile(Error),
+    #[error("could not find a package.json in the current directory ({0})")]
+    NoPackageJson(Error),
+    #[error("package.json already exists, use --force to overwrite it")]
+    PackageJsonExists,
+    #[error("failed to read input ({0})")]
+    FailedToReadInput(Error),
+    #[error("'{0}' is a local path, only a local package can depend on one")]
+    LocalDependency(String),
+    #[error("the package.json of '{0}' has no name")]
+    MissingPackageName(String),
+    #[error("the workspace '{0}' has no package.json")]
+    MissingWorkspace(String),
+    #[error("invalid workspace pattern '{0}' ({1})")]
+    InvalidWorkspacePattern(String, glob::PatternError),
+    #[error("the tarball '{0}' doesn't contain a package.json")]
+    MissingTarballPackageJson(String),
+    #[error("package.json must contain a JSON object")]
+    InvalidPackageJsonRoot,
+    #[error("'{0}' is not a valid package.json ({1})")]
+    InvalidPackageJson(String, serde_json::Error),
+    #[error("failed to serialize package.json ({0})")]
+    FailedToSerializePackageJson(serde_json::Error),
+    #[error("failed to serialize report ({0})")]
+    FailedToSerializeReport(serde_json::Error),
+    #[error("invalid dependency range ({0})")]
+    InvalidDependencyRange(ParseError),
+    #[error("failed to install '{0}' ({1})")]
+    InstallFailed(String, String),
+    #[error("the extraction task stopped before '{0}' could be extracted")]
+    ExtractionStopped(String),
+    #[error("a background task panicked ({0})")]
+    TaskPanicked(String),
+    #[error("{0} background task(s) failed, the install is incomplete")]
+    TasksFailed(usize),
+    #[error("{0} background task(s) still running after {1}s, the install is stuck")]
+    InstallStuck(usize, u64),
+    #[error("no installed package provides the command '{0}'")]
+    CommandNotInstalled(String),
+    #[error("failed to run '{0}' ({1})")]
+    FailedToRunCommand(String, Error),
+    #[error("'{0}' exited with code {1}")]
+    CommandFailed(String, i32),
+    #[error("the postinstall script of '{0}' failed ({1})")]
+    ScriptFailed(String, String),
+    #[error("{0} package(s) were asked for with conflicting ranges")]
+    ConflictingRanges(usize),
+    #[error("{0} package(s) failed to install")]
+    PackagesFailed(usize),
+    #[error("failed to read file ({0})")]
+    FailedToReadFile(Error),
+    #[error("failed to remove file ({0})")]
+    FailedToRemoveFile(Error),
+    #[error("could not find a pie-lock.json in the current directory ({0})")]
+    NoProjectLock(Error),
+    #[error("pie-lock.json has version {0}, which is newer than this version of pie supports")]
+    UnsupportedLockfileVersion(u32),
+    #[error(
+        "pie-lock.json has no version of '{0}' matching '{1}', run `pie install` to update it"
+    )]
+    LockfileOutOfDate(String, String),
+    #[error(
+        "node_modules doesn't match pie-lock.json ({0} problem(s)), run `pie install` to fix it"
+    )]
+    NodeModulesOutOfDate(usize),
+    #[error("'{0}' is not a dependency of package.json")]
+    NotADependency(String),
+    #[error("failed to change the working directory ({0})")]
+    FailedToChangeDir(Error),
+}
+
+/// Why a run of pie failed, which decides its exit code.
+#[derive(Debug)]
+pub enum PieError {
+    Parse(ParseError),
+    Command(CommandError),
+}
+
+impl PieError {
+    /// Scripts can tell a command that was misused, exiting with 2, from one that failed.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            PieError::Parse(_) => 2,
+            // `pie exec` exits like the command it ran
+            PieError::Command(CommandError::CommandFailed(_, code)) => *code,
+            PieError::Command(_) => 1,
+        }
+    }
 }
@@ -0,0 +1,122 @@
+use crate::package_json::PackageJson;
+use crate::types::{LockedPackage, ProjectLock};
+use crate::versions::Versions;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashSet};
+
+/// The project itself when its package.json has no name.
+const ROOT: &str = "package.json";
+
+/// A dependency of a package, with the range it asked for when the lockfile recorded it.
+#[derive(Serialize)]
+pub struct Edge {
+    pub package: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub range: Option<String>,
+}
+
+/// The resolved dependency graph of a project, as an adjacency list by `name@version`.
+#[derive(Serialize)]
+pub struct DependencyGraph {
+    pub root: String,
+    pub packages: BTreeMap<String, Vec<Edge>>,
+}
+
+impl DependencyGraph {
+    /// Builds the graph of `pie-lock.json`. The project is the root, depending on the
+    /// packages of its package.json.
+    pub fn from_lock(project_lock: &ProjectLock) -> Self {
+        let package_json = PackageJson::read().ok();
+        let root = package_json
+            .as_ref()
+            .and_then(|package_json| package_json.name.clone())
+            .unwrap_or(String::from(ROOT));
+
+        let declared = package_json
+            .map(|package_json| {
+                package_json
+                    .dependencies
+                    .into_iter()
+                    .chain(package_json.dev_dependencies)
+                    .collect::<BTreeMap<_, _>>()
+            })
+            .unwrap_or_default();
+        let root_edges = project_lock
+            .dependencies
+            .iter()
+            .map(|(name, version)| Edge {
+                package: Versions::stringify(name, version),
+                range: declared.get(name).cloned(),
+            })
+            .collect();
+
+        let mut packages = BTreeMap::new();
+        packages.insert(root.to_string(), root_edges);
+        for (package, locked_package) in project_lock.packages.iter() {
+            packages.insert(
+                package.to_string(),
+                Self::edges(project_lock, locked_package),
+            );
+        }
+
+        Self { root, packages }
+    }
+
+    /// The direct dependencies of a package. A lockfile written before they were recorded
+    /// only has the whole closure, the ones reachable through another dependency are then
+    /// dropped.
+    fn edges(project_lock: &ProjectLock, locked_package: &LockedPackage) -> Vec<Edge> {
+        if !locked_package.requested.is_empty() {
+            return locked_package
+                .requested
+                .iter()
+                .map(|(name, requested)| Edge {
+                    package: Versions::stringify(name, &requested.version),
+                    range: Some(requested.range.to_string()),
+                })
+                .collect();
+        }
+
+        let closure = locked_package.dependencies.iter().collect::<HashSet<_>>();
+        let mut direct = closure
+            .iter()
+            .filter(|dependency| {
+                !closure.iter().any(|other| {
+                    other != *dependency
+                        && project_lock
+                            .packages
+                            .get(*other)
+                            .is_some_and(|other| other.dependencies.contains(dependency))
+                })
+            })
+            .map(|dependency| Edge {
+                package: dependency.to_string(),
+                range: None,
+            })
+            .collect::<Vec<_>>();
+        direct.sort_by(|a, b| a.package.cmp(&b.package));
+        direct
+    }
+
+    /// The graph in the DOT language of Graphviz, each edge labelled with its range.
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph dependencies {\n");
+        dot.push_str(&format!("  {:?} [shape=box];\n", self.root));
+
+        for (package, edges) in self.packages.iter() {
+            if edges.is_empty() {
+                dot.push_str(&format!("  {package:?};\n"));
+            }
+            for edge in edges {
+                dot.push_str(&format!("  {package:?} -> {:?}", edge.package));
+                if let Some(range) = &edge.range {
+                    dot.push_str(&format!(" [label={range:?}]"));
+                }
+                dot.push_str(";\n");
+            }
+        }
+
+        dot.push('}');
+        dot
+    }
+}
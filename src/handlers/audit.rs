@@ -0,0 +1,180 @@
+use crate::command_handler::{Args, CommandHandler};
+use crate::config::CONFIG;
+use crate::errors::{CommandError, ParseError};
+use crate::http::HttpRequest;
+use crate::package_json::PackageJson;
+use crate::report::{Reporter, Vulnerability};
+use crate::types::{Advisory, ProjectLock, Severity};
+use crate::utils;
+use crate::versions::Versions;
+use async_trait::async_trait;
+use semver::Version;
+use std::collections::{BTreeMap, BTreeSet};
+
+#[derive(Default)]
+pub struct AuditHandler {
+    production: bool,
+    audit_level: Option<Severity>,
+}
+
+impl AuditHandler {
+    /// The installed versions of each package of the lockfile, or only of the ones the
+    /// `dependencies` of package.json need in production.
+    fn installed_versions(
+        project_lock: &ProjectLock,
+        production: bool,
+    ) -> Result<BTreeMap<String, Vec<String>>, CommandError> {
+        let mut packages = project_lock.packages.keys().collect::<BTreeSet<_>>();
+        if production {
+            let dependencies = PackageJson::read()?.dependencies;
+            let roots = project_lock
+                .dependencies
+                .iter()
+                .filter(|(name, _)| dependencies.contains_key(*name))
+                .map(|(name, version)| Versions::stringify(name, version));
+
+            packages = BTreeSet::new();
+            for root in roots {
+                let Some((root, locked_package)) = project_lock.packages.get_key_value(&root)
+                else {
+                    continue;
+                };
+                packages.insert(root);
+                packages.extend(locked_package.dependencies.iter());
+            }
+        }
+
+        let mut versions: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        for package in packages {
+            let (name, version) = Versions::parse_raw_package_details(package.to_string());
+            versions.entry(name).or_default().push(version);
+        }
+        Ok(versions)
+    }
+
+    /// Whether an advisory concerns a version. One whose range can't be read is trusted to
+    /// concern every version the registry was asked about.
+    fn affects(advisory: &Advisory, version: &str) -> bool {
+        match (
+            Versions::parse_semantic_version(&advisory.vulnerable_versions),
+            Version::parse(version),
+        ) {
+            (Ok(range), Ok(version)) => range.matches(&version),
+            _ => true,
+        }
+    }
+}
+
+#[async_trait]
+impl CommandHandler for AuditHandler {
+    fn parse(&mut self, args: &mut Args) -> Result<(), ParseError> {
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--production" | "--omit=dev" => self.production = true,
+                "--audit-level" => {
+                    let level = args
+                        .next()
+                        .ok_or(ParseError::MissingArgument(String::from("audit_level")))?;
+                    self.audit_level = Some(
+                        level
+                            .parse()
+                            .map_err(|_| ParseError::InvalidArgument(level))?,
+                    );
+                }
+                _ if arg.starts_with("--audit-level=") => {
+                    let level = &arg["--audit-level=".len()..];
+                    self.audit_level = Some(
+                        level
+                            .parse()
+                            .map_err(|_| ParseError::InvalidArgument(arg.to_string()))?,
+                    );
+                }
+                _ => return Err(ParseError::InvalidArgument(arg)),
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn execute(&self) -> Result<(), CommandError> {
+        let project_lock = ProjectLock::read()?;
+        let versions = Self::installed_versions(&project_lock, self.production)?;
+        let advisories = match versions.is_empty() {
+            true => Default::default(),
+            false => HttpRequest::advisories(HttpRequest::client(), &versions).await?,
+        };
+
+        let mut vulnerabilities = Vec::new();
+        for (name, advisories) in advisories {
+            for version in versions.get(&name).into_iter().flatten() {
+                for advisory in advisories.iter() {
+                    if Self::affects(advisory, version) {
+                        vulnerabilities.push(Vulnerability {
+                            package: name.to_string(),
+                            version: version.to_string(),
+                            severity: advisory.severity,
+                            title: advisory.title.to_string(),
+                            url: advisory.url.to_string(),
+                        });
+                    }
+                }
+            }
+        }
+        // the most severe first
+        vulnerabilities.sort_by(|a, b| {
+            b.severity
+                .cmp(&a.severity)
+                .then_with(|| a.package.cmp(&b.package))
+                .then_with(|| a.version.cmp(&b.version))
+        });
+
+        let audit_level = self.audit_level.or(CONFIG.audit_level).unwrap_or_default();
+        let failing = vulnerabilities
+            .iter()
+            .filter(|vulnerability| vulnerability.severity >= audit_level)
+            .count();
+
+        if Reporter::is_json() {
+            Reporter::print(&vulnerabilities)?;
+        } else if vulnerabilities.is_empty() {
+            println!("No known vulnerabilities found");
+        } else {
+            let mut rows = vulnerabilities
+                .iter()
+                .map(|vulnerability| {
+                    [
+                        Versions::stringify(&vulnerability.package, &vulnerability.version),
+                        vulnerability.severity.as_str().to_string(),
+                        vulnerability.title.to_string(),
+                        vulnerability.url.to_string(),
+                    ]
+                })
+                .collect::<Vec<_>>();
+            rows.insert(0, ["package", "severity", "title", "url"].map(String::from));
+            utils::print_table(&rows);
+
+            let mut counts: BTreeMap<Severity, usize> = BTreeMap::new();
+            for vulnerability in vulnerabilities.iter() {
+                *counts.entry(vulnerability.severity).or_default() += 1;
+            }
+            let counts = counts
+                .iter()
+                .rev()
+                .map(|(severity, count)| format!("{count} {}", severity.as_str()))
+                .collect::<Vec<_>>();
+            println!(
+                "\n{} vulnerability(ies) found ({})",
+                vulnerabilities.len(),
+                counts.join(", ")
+            );
+        }
+
+        match failing {
+            0 => Ok(()),
+            _ => Err(CommandError::VulnerabilitiesFound(
+                failing,
+                audit_level.as_str().to_string(),
+            )),
+        }
+    }
+}
@@ -0,0 +1,176 @@
+use crate::cache::{Cache, CACHE_DIR, CACHE_MAX};
+use crate::command_handler::{Args, CommandHandler};
+use crate::errors::{CommandError, ParseError};
+use crate::utils;
+use crate::versions::Versions;
+use async_trait::async_trait;
+use log::info;
+use std::fs;
+use std::path::Path;
+
+#[derive(Default)]
+enum CacheCommand {
+    #[default]
+    Clean,
+    Verify,
+    Gc,
+}
+
+#[derive(Default)]
+pub struct CacheHandler {
+    command: CacheCommand,
+    package_name: Option<String>,
+    remove_corrupt: bool,
+}
+
+impl CacheHandler {
+    fn clean(&self) -> Result<(), CommandError> {
+        if !Path::new(CACHE_DIR.as_str()).exists() {
+            info!("The cache is already empty");
+            return Ok(());
+        }
+
+        let Some(package_name) = &self.package_name else {
+            let reclaimed = utils::dir_size(Path::new(CACHE_DIR.as_str()));
+            fs::remove_dir_all(CACHE_DIR.as_str()).map_err(CommandError::FailedToRemoveFile)?;
+
+            info!("Cache cleaned, {} reclaimed", utils::format_size(reclaimed));
+            return Ok(());
+        };
+
+        let entries = Cache::list_entries()
+            .into_iter()
+            .filter(|entry| {
+                &Versions::parse_raw_package_details(entry.to_string()).0 == package_name
+            })
+            .collect::<Vec<_>>();
+
+        if entries.is_empty() {
+            info!("Package '{}' is not in the cache", package_name);
+            return Ok(());
+        }
+
+        let mut reclaimed = 0;
+        for entry in entries {
+            let path = format!("{}/{}", *CACHE_DIR, entry);
+            reclaimed += utils::dir_size(Path::new(&path));
+            fs::remove_dir_all(&path).map_err(CommandError::FailedToRemoveFile)?;
+            info!("Removed '{}' from the cache", entry);
+        }
+
+        info!("{} reclaimed", utils::format_size(reclaimed));
+        Ok(())
+    }
+
+    fn gc(&self) -> Result<(), CommandError> {
+        let max_size = CACHE_MAX.ok_or(CommandError::NoCacheLimit)?;
+        let (evicted, reclaimed) = Cache::gc(max_size)?;
+
+        if evicted.is_empty() {
+            info!("The cache fits in {}", utils::format_size(max_size));
+            return Ok(());
+        }
+
+        for entry in evicted.iter() {
+            info!("Removed '{}' from the cache", entry);
+        }
+        info!(
+            "Evicted {} package(s), {} reclaimed",
+            evicted.len(),
+            utils::format_size(reclaimed)
+        );
+        Ok(())
+    }
+
+    /// Tells why a cache entry can't be used, if it can't.
+    fn check_entry(entry: &String) -> Option<String> {
+        if let Err(e) = Cache::read_lock(entry) {
+            return Some(format!("unreadable lockfile ({e})"));
+        }
+
+        let package_dir = format!("{}/{}/package", *CACHE_DIR, entry);
+        let has_contents = fs::read_dir(package_dir).is_ok_and(|mut files| {
+            files.any(|file| file.is_ok_and(|file| file.file_name() != "pie-lock.json"))
+        });
+
+        match has_contents {
+            true => None,
+            false => Some(String::from("empty package directory")),
+        }
+    }
+
+    fn verify(&self) -> Result<(), CommandError> {
+        if !Path::new(CACHE_DIR.as_str()).exists() {
+            info!("The cache is empty");
+            return Ok(());
+        }
+
+        let mut entries = Cache::list_entries();
+        entries.sort();
+
+        let corrupt = entries
+            .iter()
+            .filter_map(|entry| Self::check_entry(entry).map(|reason| (entry, reason)))
+            .collect::<Vec<_>>();
+
+        if corrupt.is_empty() {
+            info!("All {} cache entries are valid", entries.len());
+            return Ok(());
+        }
+
+        info!("Found {} corrupt cache entries:", corrupt.len());
+        for (entry, reason) in corrupt.iter() {
+            info!("  {entry}: {reason}");
+        }
+
+        if !self.remove_corrupt {
+            info!("Run `pie cache verify --remove` to delete them");
+            return Ok(());
+        }
+
+        for (entry, _) in corrupt {
+            fs::remove_dir_all(format!("{}/{}", *CACHE_DIR, entry))
+                .map_err(CommandError::FailedToRemoveFile)?;
+            info!("Removed '{}' from the cache", entry);
+        }
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl CommandHandler for CacheHandler {
+    fn parse(&mut self, args: &mut Args) -> Result<(), ParseError> {
+        let command = args
+            .next()
+            .ok_or(ParseError::MissingArgument(String::from("cache_command")))?;
+
+        self.command = match command.as_str() {
+            "clean" => CacheCommand::Clean,
+            "verify" => CacheCommand::Verify,
+            "gc" => CacheCommand::Gc,
+            _ => return Err(ParseError::InvalidArgument(command)),
+        };
+
+        for arg in args {
+            match (&self.command, arg.as_str()) {
+                (CacheCommand::Verify, "--remove") => self.remove_corrupt = true,
+                (CacheCommand::Clean, _) if !arg.starts_with('-') => {
+                    let (package_name, _) = Versions::parse_raw_package_details(arg);
+                    self.package_name = Some(package_name);
+                }
+                _ => return Err(ParseError::InvalidArgument(arg)),
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn execute(&self) -> Result<(), CommandError> {
+        match self.command {
+            CacheCommand::Clean => self.clean(),
+            CacheCommand::Verify => self.verify(),
+            CacheCommand::Gc => self.gc(),
+        }
+    }
+}
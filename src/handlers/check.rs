@@ -0,0 +1,78 @@
+use crate::cache::Cache;
+use crate::command_handler::{Args, CommandHandler};
+use crate::errors::{CommandError, ParseError};
+use crate::types::ProjectLock;
+use crate::versions::Versions;
+use async_trait::async_trait;
+use log::info;
+use std::collections::BTreeMap;
+
+#[derive(Default)]
+pub struct CheckHandler;
+
+impl CheckHandler {
+    /// Compares the packages of `./node_modules` with the lockfile, each difference is a line
+    /// of the report.
+    fn problems(project_lock: &ProjectLock, linked: &BTreeMap<String, String>) -> Vec<String> {
+        let locked_versions = project_lock
+            .locked_versions()
+            .into_iter()
+            .collect::<BTreeMap<_, _>>();
+        let mut problems = Vec::new();
+
+        // only one of the locked versions of a package can be linked under its name
+        for (name, versions) in locked_versions.iter() {
+            let locked = versions.join(" || ");
+            match linked.get(name) {
+                None => problems.push(format!("missing: {}", Versions::stringify(name, &locked))),
+                Some(version) if !versions.contains(version) => problems.push(format!(
+                    "mismatch: {} is installed, pie-lock.json has {locked}",
+                    Versions::stringify(name, version),
+                )),
+                Some(_) => {}
+            }
+        }
+
+        for (name, version) in linked.iter() {
+            let package = Versions::stringify(name, version);
+            if !locked_versions.contains_key(name) {
+                problems.push(format!("extra: {package}"));
+            }
+            // a link is left dangling once its cache entry is removed
+            if !Cache::is_in_cache(name, version) {
+                problems.push(format!("not in the cache: {package}"));
+            }
+        }
+
+        problems
+    }
+}
+
+#[async_trait]
+impl CommandHandler for CheckHandler {
+    fn parse(&mut self, args: &mut Args) -> Result<(), ParseError> {
+        match args.next() {
+            Some(arg) => Err(ParseError::InvalidArgument(arg)),
+            None => Ok(()),
+        }
+    }
+
+    async fn execute(&self) -> Result<(), CommandError> {
+        let project_lock = ProjectLock::read()?;
+        let linked = Cache::linked_packages()?
+            .into_iter()
+            .map(Versions::parse_raw_package_details)
+            .collect::<BTreeMap<_, _>>();
+
+        let problems = Self::problems(&project_lock, &linked);
+        if problems.is_empty() {
+            info!("node_modules matches pie-lock.json");
+            return Ok(());
+        }
+
+        for problem in problems.iter() {
+            println!("{problem}");
+        }
+        Err(CommandError::NodeModulesOutOfDate(problems.len()))
+    }
+}
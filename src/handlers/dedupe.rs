@@ -0,0 +1,246 @@
+use crate::cache::Cache;
+use crate::command_handler::{Args, CommandHandler};
+use crate::errors::{CommandError, ParseError};
+use crate::package_json::PackageJson;
+use crate::types::{PackageLock, ProjectLock};
+use crate::utils;
+use crate::versions::Versions;
+use async_trait::async_trait;
+use log::info;
+use semver::Version;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+
+#[derive(Default)]
+pub struct DedupeHandler;
+
+impl DedupeHandler {
+    /// Reads the lock of every package reachable from the linked ones.
+    fn read_locks(linked: &[String]) -> Result<HashMap<String, PackageLock>, CommandError> {
+        let mut locks = HashMap::new();
+        let mut pending = linked.to_vec();
+
+        while let Some(package) = pending.pop() {
+            if locks.contains_key(&package) {
+                continue;
+            }
+
+            let lock = Cache::read_lock(&package)?;
+            pending.extend(lock.dependencies.iter().cloned());
+            locks.insert(package, lock);
+        }
+
+        Ok(locks)
+    }
+
+    /// The direct dependencies of a package. A lock written before they were recorded only
+    /// has its whole closure.
+    fn direct_dependencies(lock: &PackageLock) -> Vec<String> {
+        if lock.requested.is_empty() {
+            return lock.dependencies.to_vec();
+        }
+
+        lock.requested
+            .iter()
+            .map(|(name, requested)| Versions::stringify(name, &requested.version))
+            .collect()
+    }
+
+    /// Picks, for every package resolved to several versions, the highest of them satisfying
+    /// the range of each of its dependents.
+    fn consolidate(
+        locks: &HashMap<String, PackageLock>,
+        linked: &[String],
+    ) -> BTreeMap<String, String> {
+        let mut versions: BTreeMap<String, Vec<Version>> = BTreeMap::new();
+        for package in locks.keys() {
+            let (name, version) = Versions::parse_raw_package_details(package.to_string());
+            if let Ok(version) = Version::parse(&version) {
+                versions.entry(name).or_default().push(version);
+            }
+        }
+
+        let mut requested: HashMap<String, Vec<String>> = HashMap::new();
+        for lock in locks.values() {
+            for (name, dependency) in lock.requested.iter() {
+                requested
+                    .entry(name.to_string())
+                    .or_default()
+                    .push(dependency.range.to_string());
+            }
+        }
+
+        let declared = PackageJson::read()
+            .map(|package_json| {
+                package_json
+                    .dependencies
+                    .into_iter()
+                    .chain(package_json.dev_dependencies)
+                    .collect::<HashMap<_, _>>()
+            })
+            .unwrap_or_default();
+
+        // a package installed directly without being saved must keep its version
+        let required = locks
+            .values()
+            .flat_map(|lock| lock.dependencies.iter())
+            .collect::<HashSet<_>>();
+        for root in linked.iter().filter(|package| !required.contains(package)) {
+            let (name, version) = Versions::parse_raw_package_details(root.to_string());
+            let range = declared
+                .get(&name)
+                .cloned()
+                .unwrap_or(format!("={version}"));
+            requested.entry(name).or_default().push(range);
+        }
+
+        let mut chosen = BTreeMap::new();
+        for (name, mut versions) in versions {
+            if versions.len() < 2 {
+                continue;
+            }
+
+            // without the ranges of every dependent, a version can't be known to fit them all
+            let has_unknown_dependent = locks.values().any(|lock| {
+                lock.requested.is_empty()
+                    && lock.dependencies.iter().any(|dependency| {
+                        Versions::parse_raw_package_details(dependency.to_string()).0 == name
+                    })
+            });
+            if has_unknown_dependent {
+                continue;
+            }
+
+            let Ok(ranges) = requested
+                .get(&name)
+                .into_iter()
+                .flatten()
+                .map(|range| Versions::parse_semantic_version(range))
+                .collect::<Result<Vec<_>, _>>()
+            else {
+                continue;
+            };
+
+            versions.sort();
+            let version = versions
+                .iter()
+                .rev()
+                .find(|version| ranges.iter().all(|range| range.matches(version)));
+
+            if let Some(version) = version {
+                chosen.insert(name, version.to_string());
+            }
+        }
+
+        chosen
+    }
+
+    /// Every package reachable from `package`, once each dependency is replaced by its chosen version.
+    fn closure(
+        package: &String,
+        graph: &HashMap<String, Vec<String>>,
+        chosen: &BTreeMap<String, String>,
+    ) -> Vec<String> {
+        let substitute = |dependency: &String| {
+            let (name, _) = Versions::parse_raw_package_details(dependency.to_string());
+            match chosen.get(&name) {
+                Some(version) => Versions::stringify(&name, version),
+                None => dependency.to_string(),
+            }
+        };
+
+        let mut closure = HashSet::new();
+        let mut pending = graph
+            .get(package)
+            .into_iter()
+            .flatten()
+            .map(substitute)
+            .collect::<Vec<_>>();
+
+        while let Some(dependency) = pending.pop() {
+            if &dependency == package || !closure.insert(dependency.to_string()) {
+                continue;
+            }
+            pending.extend(graph.get(&dependency).into_iter().flatten().map(substitute));
+        }
+
+        let mut closure = closure.into_iter().collect::<Vec<_>>();
+        closure.sort();
+        closure
+    }
+}
+
+#[async_trait]
+impl CommandHandler for DedupeHandler {
+    fn parse(&mut self, args: &mut Args) -> Result<(), ParseError> {
+        match args.next() {
+            Some(arg) => Err(ParseError::InvalidArgument(arg)),
+            None => Ok(()),
+        }
+    }
+
+    async fn execute(&self) -> Result<(), CommandError> {
+        let linked = Cache::linked_packages()?;
+        let mut locks = Self::read_locks(&linked)?;
+        let chosen = Self::consolidate(&locks, &linked);
+
+        let duplicates = locks
+            .keys()
+            .filter(|package| {
+                let (name, version) = Versions::parse_raw_package_details(package.to_string());
+                chosen.get(&name).is_some_and(|chosen| chosen != &version)
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+
+        if duplicates.is_empty() {
+            info!("No duplicate packages found");
+            return Ok(());
+        }
+
+        // the cached locks are rewritten, so every project using them benefits from the dedupe
+        let graph = locks
+            .iter()
+            .map(|(package, lock)| (package.to_string(), Self::direct_dependencies(lock)))
+            .collect::<HashMap<_, _>>();
+        for (package, lock) in locks.iter_mut() {
+            let mut dependencies = lock.dependencies.to_vec();
+            dependencies.sort();
+
+            let closure = Self::closure(package, &graph, &chosen);
+            let mut changed = closure != dependencies;
+            lock.dependencies = closure;
+
+            for (name, requested) in lock.requested.iter_mut() {
+                if let Some(version) = chosen.get(name).filter(|v| **v != requested.version) {
+                    requested.version = version.to_string();
+                    changed = true;
+                }
+            }
+
+            if changed {
+                Cache::write_lock(package, lock)?;
+            }
+        }
+
+        for (name, version) in chosen.iter() {
+            let link = format!("./node_modules/{}", name);
+            if fs::symlink_metadata(&link).is_ok() {
+                utils::unlink_dir(Path::new(&link)).map_err(CommandError::FailedToRemoveFile)?;
+            }
+            Cache::load_cached_version(Versions::stringify(name, version))?;
+        }
+
+        ProjectLock::collect()?.write()?;
+
+        let mut duplicates = duplicates;
+        duplicates.sort();
+        for duplicate in duplicates.iter() {
+            let (name, _) = Versions::parse_raw_package_details(duplicate.to_string());
+            info!("{} -> {}", duplicate, chosen[&name]);
+        }
+        info!("Removed {} duplicate package(s)", duplicates.len());
+        Ok(())
+    }
+}
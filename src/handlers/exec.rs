@@ -0,0 +1,155 @@
+use crate::cache::CACHE_DIR;
+use crate::command_handler::{Args, CommandHandler};
+use crate::errors::{CommandError, ParseError};
+use crate::handlers::init::InitHandler;
+use crate::handlers::install::InstallHandler;
+use async_trait::async_trait;
+use log::info;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Where the packages run without being installed in the project are installed, one
+/// directory laid out like a project per package.
+const EXEC_DIR: &str = ".exec";
+
+#[derive(Default)]
+pub struct ExecHandler {
+    command: String,
+    args: Vec<String>,
+    /// The package providing the command, the one named like it when not given.
+    package: Option<String>,
+    yes: bool,
+}
+
+impl ExecHandler {
+    /// The directory the package of the command is installed to when the project lacks it.
+    fn exec_dir(&self) -> PathBuf {
+        let package = self.package.as_ref().unwrap_or(&self.command);
+        Path::new(CACHE_DIR.as_str())
+            .join(EXEC_DIR)
+            .join(package.replace('/', "%2f"))
+    }
+
+    /// Installs the package of the command into its exec directory, once the user agreed to.
+    async fn install(&self, dir: &Path) -> Result<(), CommandError> {
+        let package = self.package.as_ref().unwrap_or(&self.command);
+        if !self.yes {
+            let answer =
+                InitHandler::prompt(&format!("Need to install '{package}', ok to proceed?"), "y")?;
+            if !answer.eq_ignore_ascii_case("y") && !answer.eq_ignore_ascii_case("yes") {
+                return Err(CommandError::CommandNotInstalled(self.command.to_string()));
+            }
+        }
+
+        let current_dir = env::current_dir().map_err(CommandError::FailedToChangeDir)?;
+        fs::create_dir_all(dir).map_err(CommandError::FailedToCreateDir)?;
+        env::set_current_dir(dir).map_err(CommandError::FailedToChangeDir)?;
+
+        let mut install_handler = InstallHandler::default();
+        let mut args = vec![package.to_string(), String::from("--no-save")].into_iter();
+        let installed = match install_handler.parse(&mut args) {
+            Ok(_) => install_handler.execute().await,
+            Err(e) => Err(CommandError::InvalidDependencyRange(e)),
+        };
+
+        env::set_current_dir(current_dir).map_err(CommandError::FailedToChangeDir)?;
+        if installed.is_err() {
+            let _ = fs::remove_dir_all(dir);
+        }
+        installed
+    }
+
+    fn run(&self, bin_dir: &Path) -> Result<(), CommandError> {
+        let bin = bin_dir.join(&self.command);
+        let paths = env::var_os("PATH").unwrap_or_default();
+        let path = env::join_paths(
+            [bin_dir.to_path_buf()]
+                .into_iter()
+                .chain(env::split_paths(&paths)),
+        )
+        .map_err(|_| CommandError::CommandNotInstalled(self.command.to_string()))?;
+
+        // the executables are linked scripts, which Windows can't run by themselves
+        #[cfg(windows)]
+        let mut command = {
+            let mut command = Command::new("node");
+            command.arg(&bin);
+            command
+        };
+        #[cfg(not(windows))]
+        let mut command = Command::new(&bin);
+
+        let status = command
+            .args(&self.args)
+            .env("PATH", path)
+            .status()
+            .map_err(|e| CommandError::FailedToRunCommand(self.command.to_string(), e))?;
+
+        match status.success() {
+            true => Ok(()),
+            // a command stopped by a signal has no exit code
+            false => Err(CommandError::CommandFailed(
+                self.command.to_string(),
+                status.code().unwrap_or(1),
+            )),
+        }
+    }
+}
+
+#[async_trait]
+impl CommandHandler for ExecHandler {
+    fn parse(&mut self, args: &mut Args) -> Result<(), ParseError> {
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "-y" | "--yes" => self.yes = true,
+                "-p" | "--package" => {
+                    let package = args
+                        .next()
+                        .ok_or(ParseError::MissingArgument(String::from("package")))?;
+                    self.package = Some(package);
+                }
+                _ if arg.starts_with("--package=") => {
+                    self.package = Some(arg["--package=".len()..].to_string());
+                }
+                "--" => {}
+                _ if arg.starts_with('-') => return Err(ParseError::InvalidArgument(arg)),
+                _ => {
+                    self.command = arg;
+                    break;
+                }
+            }
+        }
+
+        if self.command.is_empty() {
+            return Err(ParseError::MissingArgument(String::from("command")));
+        }
+
+        // everything after the command is its own, a `--` in between is only a separator
+        self.args = args.collect();
+        if self.args.first().is_some_and(|arg| arg == "--") {
+            self.args.remove(0);
+        }
+        Ok(())
+    }
+
+    async fn execute(&self) -> Result<(), CommandError> {
+        let bin_dir = PathBuf::from("./node_modules/.bin");
+        if bin_dir.join(&self.command).exists() {
+            return self.run(&bin_dir);
+        }
+
+        let exec_dir = self.exec_dir();
+        let exec_bin_dir = exec_dir.join("node_modules/.bin");
+        if !exec_bin_dir.join(&self.command).exists() {
+            info!("'{}' isn't installed in the project", self.command);
+            self.install(&exec_dir).await?;
+        }
+
+        if !exec_bin_dir.join(&self.command).exists() {
+            return Err(CommandError::CommandNotInstalled(self.command.to_string()));
+        }
+        self.run(&exec_bin_dir)
+    }
+}
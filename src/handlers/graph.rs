@@ -0,0 +1,30 @@
+use crate::command_handler::{Args, CommandHandler};
+use crate::errors::{CommandError, ParseError};
+use crate::graph::DependencyGraph;
+use crate::report::Reporter;
+use crate::types::ProjectLock;
+use async_trait::async_trait;
+
+#[derive(Default)]
+pub struct GraphHandler;
+
+#[async_trait]
+impl CommandHandler for GraphHandler {
+    fn parse(&mut self, args: &mut Args) -> Result<(), ParseError> {
+        match args.next() {
+            Some(arg) => Err(ParseError::InvalidArgument(arg)),
+            None => Ok(()),
+        }
+    }
+
+    async fn execute(&self) -> Result<(), CommandError> {
+        let graph = DependencyGraph::from_lock(&ProjectLock::read()?);
+
+        if Reporter::is_json() {
+            return Reporter::print(&graph);
+        }
+
+        println!("{}", graph.to_dot());
+        Ok(())
+    }
+}
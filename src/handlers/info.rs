@@ -0,0 +1,111 @@
+use crate::command_handler::{Args, CommandHandler};
+use crate::errors::{CommandError, ParseError};
+use crate::http::HttpRequest;
+use crate::types::VersionData;
+use crate::utils::LATEST;
+use crate::versions::Versions;
+use async_trait::async_trait;
+use reqwest::Client;
+
+#[derive(Default)]
+pub struct InfoHandler {
+    package_name: String,
+    package_version: String,
+}
+
+impl InfoHandler {
+    fn print_dependencies(version_data: &VersionData) {
+        let mut dependencies = version_data
+            .dependencies
+            .iter()
+            .flatten()
+            .collect::<Vec<_>>();
+        dependencies.sort();
+
+        println!("dependencies ({}):", dependencies.len());
+        for (name, range) in dependencies {
+            println!("  {name}: {range}");
+        }
+    }
+
+    async fn print_package(&self, client: Client) -> Result<(), CommandError> {
+        let package_data = HttpRequest::package_data(client, &self.package_name).await?;
+
+        let mut versions = package_data.versions.iter().collect::<Vec<_>>();
+        Versions::sort(&mut versions);
+
+        let latest = package_data
+            .dist_tags
+            .get(LATEST)
+            .and_then(|latest| package_data.versions.get(latest))
+            .or(versions.last().map(|(_, version_data)| *version_data))
+            .ok_or(CommandError::InvalidVersion(String::from(LATEST)))?;
+
+        println!("{}", Versions::stringify(&latest.name, &latest.version));
+        if let Some(published) = package_data.published(&latest.version) {
+            println!("published: {published}");
+        }
+        println!(
+            "dependencies: {}",
+            latest.dependencies.as_ref().map_or(0, |d| d.len())
+        );
+
+        if let Some(modified) = package_data.last_modified() {
+            println!("modified: {modified}");
+        }
+
+        let mut dist_tags = package_data.dist_tags.iter().collect::<Vec<_>>();
+        dist_tags.sort();
+        println!("dist-tags:");
+        for (tag, version) in dist_tags {
+            println!("  {tag}: {version}");
+        }
+
+        let versions = versions
+            .iter()
+            .map(|(version, _)| version.as_str())
+            .collect::<Vec<_>>();
+        println!("versions ({}): {}", versions.len(), versions.join(", "));
+
+        Ok(())
+    }
+
+    async fn print_version(&self, client: Client) -> Result<(), CommandError> {
+        let version_data =
+            HttpRequest::version_data(client, &self.package_name, &self.package_version).await?;
+
+        println!(
+            "{}",
+            Versions::stringify(&version_data.name, &version_data.version)
+        );
+        println!("tarball: {}", version_data.dist.tarball);
+        Self::print_dependencies(&version_data);
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl CommandHandler for InfoHandler {
+    fn parse(&mut self, args: &mut Args) -> Result<(), ParseError> {
+        let package = args
+            .next()
+            .ok_or(ParseError::MissingArgument(String::from("package_name")))?;
+
+        let (package_name, package_version) = Versions::parse_raw_package_details(package);
+        self.package_name = package_name;
+        self.package_version = package_version;
+
+        Ok(())
+    }
+
+    async fn execute(&self) -> Result<(), CommandError> {
+        let client = HttpRequest::client();
+
+        // a bare package name shows the whole package, a version shows only that version
+        match self.package_version == LATEST {
+            true => self.print_package(client).await,
+            false => self.print_version(client).await,
+        }
+    }
+}
@@ -0,0 +1,92 @@
+use crate::command_handler::{Args, CommandHandler};
+use crate::errors::{CommandError, ParseError};
+use crate::package_json::PACKAGE_JSON;
+use async_trait::async_trait;
+use log::info;
+use serde_json::{json, Map, Value};
+use std::env;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+
+const DEFAULT_VERSION: &str = "1.0.0";
+const TEST_SCRIPT: &str = "echo \"Error: no test specified\" && exit 1";
+
+#[derive(Default)]
+pub struct InitHandler {
+    yes: bool,
+    force: bool,
+}
+
+impl InitHandler {
+    /// The name of the current directory, made a valid package name.
+    fn default_name() -> String {
+        env::current_dir()
+            .ok()
+            .and_then(|dir| Some(dir.file_name()?.to_string_lossy().to_string()))
+            .map(|name| name.trim().to_lowercase().replace(char::is_whitespace, "-"))
+            .filter(|name| !name.is_empty())
+            .unwrap_or(String::from("package"))
+    }
+
+    /// Asks for a value on the standard input, an empty answer keeps the default.
+    pub fn prompt(question: &str, default: &str) -> Result<String, CommandError> {
+        print!("{question}: ({default}) ");
+        io::stdout()
+            .flush()
+            .map_err(CommandError::FailedToReadInput)?;
+
+        let mut answer = String::new();
+        io::stdin()
+            .lock()
+            .read_line(&mut answer)
+            .map_err(CommandError::FailedToReadInput)?;
+
+        match answer.trim() {
+            "" => Ok(default.to_string()),
+            answer => Ok(answer.to_string()),
+        }
+    }
+}
+
+#[async_trait]
+impl CommandHandler for InitHandler {
+    fn parse(&mut self, args: &mut Args) -> Result<(), ParseError> {
+        for arg in args {
+            match arg.as_str() {
+                "-y" | "--yes" => self.yes = true,
+                "-f" | "--force" => self.force = true,
+                _ => return Err(ParseError::InvalidArgument(arg)),
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn execute(&self) -> Result<(), CommandError> {
+        if Path::new(PACKAGE_JSON).exists() && !self.force {
+            return Err(CommandError::PackageJsonExists);
+        }
+
+        let mut name = Self::default_name();
+        let mut version = String::from(DEFAULT_VERSION);
+        if !self.yes {
+            name = Self::prompt("package name", &name)?;
+            version = Self::prompt("version", &version)?;
+        }
+
+        let mut manifest = Map::new();
+        manifest.insert(String::from("name"), Value::String(name));
+        manifest.insert(String::from("version"), Value::String(version));
+        manifest.insert(String::from("scripts"), json!({ "test": TEST_SCRIPT }));
+        manifest.insert(String::from("dependencies"), json!({}));
+        manifest.insert(String::from("devDependencies"), json!({}));
+
+        let raw = serde_json::to_string_pretty(&manifest)
+            .map_err(CommandError::FailedToSerializePackageJson)?;
+        fs::write(PACKAGE_JSON, format!("{raw}\n")).map_err(CommandError::FailedToWriteFile)?;
+
+        info!("Wrote {}:\n{}", PACKAGE_JSON, raw);
+        Ok(())
+    }
+}
@@ -1,135 +1,1099 @@
-use crate::cache::{Cache, CACHE_DIR};
-use crate::command_handler::CommandHandler;
+use crate::cache::{Cache, LinkStrategy, CACHE_DIR, CACHE_MAX};
+use crate::command_handler::{Args, CommandHandler};
+use crate::config::CONFIG;
 use crate::errors::{CommandError, ParseError};
-use crate::installer::{DependencyMapMutex, InstallContext, Installer, PackageBytes, PackageInfo};
+use crate::handlers::list::ListHandler;
+use crate::http::HttpRequest;
+use crate::installer::{
+    CacheMode, DependencyMapMutex, InstallContext, Installer, PackageBytes, PackageInfo,
+    ScriptPolicy, Tarball,
+};
+use crate::package_json::{PackageJson, PACKAGE_JSON};
+use crate::project_lock::LockedVersions;
+use crate::report::{
+    FailedPackage, InstallReport, InstalledPackage, Reporter, ResolvedPackage, Source,
+};
+use crate::types::{DependencyMap, ProjectLock};
 use crate::utils;
-use crate::utils::TaskAllocator;
-use crate::versions::Versions;
+use crate::utils::{TaskAllocator, GLOBAL_DIR};
+use crate::versions::{PackageDetails, Preference, VersionSpec, Versions};
 use async_trait::async_trait;
-use reqwest::Client;
-use semver::Comparator;
-use std::collections::HashMap;
-use std::env::Args;
+use bytes::Bytes;
+use log::{debug, error, info, warn};
+use semver::Version;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::env;
 use std::fs;
-use std::fs::File;
-use std::io::Write;
-use std::sync::atomic::AtomicBool;
-use std::sync::mpsc::channel;
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::Receiver;
 use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// The version a local package without one is installed as.
+const UNVERSIONED: &str = "0.0.0";
+
+/// How long the background tasks of an install can take before it's deemed stuck.
+const DEFAULT_INSTALL_TIMEOUT_SECS: u64 = 30 * 60;
+
+/// The packages of the workspaces of a monorepo by name, with their directory and version.
+type Workspaces = BTreeMap<String, (PathBuf, String)>;
+
+enum Resolution {
+    Cached(String),
+    Fetched(Box<PackageInfo>),
+}
 
 #[derive(Default)]
 pub struct InstallHandler {
-    package_name: String,
-    package_version: Option<Comparator>,
+    packages: Vec<PackageDetails>,
+    no_save: bool,
+    save_dev: bool,
+    production: bool,
+    global: bool,
+    frozen_lockfile: bool,
+    cache_mode: CacheMode,
+    preference: Preference,
+    dry_run: bool,
+    copy: bool,
+    save_exact: bool,
+    strict_peer: bool,
+    ignore_scripts: bool,
 }
 
 impl InstallHandler {
-    pub fn write_lockfiles(dependency_map_mx: DependencyMapMutex) -> Result<(), CommandError> {
-        let dependency_map = dependency_map_mx.lock().unwrap();
+    pub fn write_lockfiles(
+        dependency_map_mx: DependencyMapMutex,
+        overrides: &HashMap<String, String>,
+    ) -> Result<(), CommandError> {
+        let mut dependency_map = utils::lock(&dependency_map_mx);
+
+        for (package_name, lock) in dependency_map.iter_mut() {
+            lock.contents = Cache::contents_hash(package_name);
+            lock.overrides = Installer::applied_overrides(overrides, &lock.dependencies);
+            Cache::write_lock(package_name, lock)?;
+        }
+
+        Ok(())
+    }
+
+    /// Looks a package up in the cache, and falls back to the registry when it isn't there.
+    async fn resolve(
+        context: &InstallContext,
+        package_name: &String,
+        package_version: &VersionSpec,
+    ) -> Result<Resolution, CommandError> {
+        info!("Installing '{}' ...", package_name);
+
+        let tarball = match package_version {
+            VersionSpec::Tarball(url) if context.cache_mode == CacheMode::Offline => {
+                return Err(CommandError::NotCached(url.to_string()));
+            }
+            VersionSpec::Tarball(url) => Some(Installer::fetch_tarball(context, url).await?),
+            // local directories are linked before resolution, so this is a local tarball
+            VersionSpec::Local(path) => {
+                let bytes = Bytes::from(fs::read(path).map_err(CommandError::FailedToReadFile)?);
+                let version_data = Installer::read_tarball(&bytes, &package_version.to_string())?;
+                Some((version_data, bytes))
+            }
+            _ => None,
+        };
+
+        if let Some((version_data, bytes)) = tarball {
+            return Ok(Resolution::Fetched(Box::new(PackageInfo {
+                stringified: Versions::stringify(&version_data.name, &version_data.version),
+                version_data,
+                is_latest: false,
+                optional: false,
+                tarball: Some(bytes),
+            })));
+        }
+
+        let full_version =
+            Installer::resolve_full_version(context, package_name, package_version).await?;
+        let package_range = package_version.range();
+        let full_version_ref = full_version.as_ref();
+        let (is_cached, cached_version) =
+            Installer::find_cached(context, package_name, package_version, full_version_ref)
+                .await?;
+
+        if is_cached {
+            let version = cached_version.expect("Failed to get cached version");
+            return Ok(Resolution::Cached(version));
+        }
+
+        if context.cache_mode == CacheMode::Offline {
+            return Err(CommandError::NotCached(Versions::stringify(
+                package_name,
+                &package_version.to_string(),
+            )));
+        }
+
+        let version_data =
+            Installer::get_version_data(context, package_name, full_version_ref, package_range)
+                .await?;
+
+        Ok(Resolution::Fetched(Box::new(PackageInfo {
+            stringified: Versions::stringify(&version_data.name, &version_data.version),
+            is_latest: HttpRequest::is_latest(package_name, &version_data.version),
+            version_data,
+            optional: false,
+            tarball: None,
+        })))
+    }
+
+    /// How many tarballs can be extracted at the same time, one per CPU unless configured.
+    fn extraction_workers() -> usize {
+        CONFIG
+            .extraction_workers
+            .filter(|workers| *workers > 0)
+            .or(thread::available_parallelism().map(usize::from).ok())
+            .unwrap_or(1)
+    }
+
+    /// Downloads every fetched package and their dependencies, sharing one dependency map
+    /// and one pool of extraction workers so that common dependencies are only installed
+    /// once.
+    pub fn install(
+        install_context: InstallContext,
+        receiver: Receiver<PackageBytes>,
+        fetched: Vec<(String, PackageInfo)>,
+        results: &mut [(String, Result<String, CommandError>)],
+    ) -> Result<(), CommandError> {
+        // every install task owns a clone of the sender, so the channel only closes once all
+        // of them are done, and everything sent before that is still extracted
+        let receiver = Arc::new(Mutex::new(receiver));
+        for worker in 0..Self::extraction_workers() {
+            let receiver = Arc::clone(&receiver);
+            let failures = Arc::clone(&install_context.failures);
+            TaskAllocator::add_blocking_task(move || {
+                debug!("Starting extraction worker {worker}...");
+                loop {
+                    // the lock is only held while waiting for the next tarball, not while
+                    // extracting it
+                    let next = utils::lock(&receiver).recv();
+                    let Ok((package_dest, tarball)) = next else {
+                        break;
+                    };
+                    debug!("Extracting package to '{}'", package_dest);
+                    let package = package_dest
+                        .trim_start_matches(&format!("{}/", *CACHE_DIR))
+                        .to_string();
+                    let extraction = match tarball {
+                        Tarball::Buffered(bytes) => utils::extract_tarball(bytes, package_dest),
+                        Tarball::Streamed(chunks, dist) => {
+                            utils::extract_from_stream(chunks, &dist, &package, &package_dest)
+                        }
+                    };
+
+                    if let Err(e) = extraction {
+                        utils::lock(&failures).push((package, e));
+                    }
+                }
+            });
+        }
+
+        let dependency_map_mutex = Arc::clone(&install_context.dependency_map_mx);
+        let failures = Arc::clone(&install_context.failures);
+        let deprecations = Arc::clone(&install_context.deprecations);
+        let dry_run = install_context.dry_run;
+        let script_policy = install_context.script_policy;
+        let overrides = Arc::clone(&install_context.overrides);
+
+        let mut installed = Vec::new();
+        for (package_name, package_info) in fetched {
+            let stringified = package_info.stringified.to_string();
+            debug!("Installing the package '{}'", stringified);
+            let install_result =
+                Installer::install_package(install_context.clone(), package_info, Vec::new());
+
+            match install_result {
+                Ok(_) => installed.push((package_name, stringified)),
+                Err(e) => Self::set_result(results, &package_name, Err(e)),
+            }
+        }
+        // the extraction workers stop once the last sender is dropped
+        drop(install_context);
+
+        let deadline = Duration::from_secs(
+            CONFIG
+                .install_timeout
+                .unwrap_or(DEFAULT_INSTALL_TIMEOUT_SECS),
+        );
+        let done = TaskAllocator::block_until_done(deadline);
+
+        // a task that crashed may have left any package half installed, none is locked then
+        let task_errors = TaskAllocator::take_errors();
+        for e in task_errors.iter() {
+            error!("{e}");
+        }
+        done?;
+        debug!("All tasks are done!");
+        if !task_errors.is_empty() {
+            return Err(CommandError::TasksFailed(task_errors.len()));
+        }
+
+        let failures = mem::take(&mut *utils::lock(&failures));
+        let broken = Self::discard_failures(&dependency_map_mutex, failures);
+
+        if !dry_run {
+            debug!("Writing lockfiles...");
+            Self::write_lockfiles(Arc::clone(&dependency_map_mutex), &overrides)?;
+        }
+
+        let mut linked = Vec::new();
+        for (package_name, stringified) in installed {
+            match broken.get(&stringified) {
+                Some((failed, reason)) => {
+                    let e = CommandError::InstallFailed(failed.to_string(), reason.to_string());
+                    Self::set_result(results, &package_name, Err(e));
+                }
+                None if dry_run => {}
+                None => match Cache::load_cached_version(stringified.to_string()) {
+                    Ok(_) => linked.push((package_name, stringified)),
+                    Err(e) => Self::set_result(results, &package_name, Err(e)),
+                },
+            }
+        }
+
+        // the scripts run once every package and executable is linked, as they may call the
+        // ones of their dependencies. A package whose script failed is unlinked again, and
+        // left incomplete in the cache along with its dependents.
+        if !dry_run {
+            let mut script_failures = Vec::new();
+            Self::run_scripts(&dependency_map_mutex, &mut script_failures, script_policy);
+            let broken = Self::discard_failures(&dependency_map_mutex, script_failures);
+            for package in broken.keys() {
+                Cache::unlink_version(package)?;
+                Cache::invalidate(package);
+            }
+
+            for (package_name, stringified) in linked {
+                if let Some((failed, reason)) = broken.get(&stringified) {
+                    let e = CommandError::InstallFailed(failed.to_string(), reason.to_string());
+                    Self::set_result(results, &package_name, Err(e));
+                }
+            }
+        }
+
+        let unmet_peers = Installer::unmet_peer_dependencies(
+            &utils::lock(&dependency_map_mutex),
+            &Cache::linked_packages()?,
+        );
+        if !unmet_peers.is_empty() {
+            warn!("Warning: unmet peer dependencies:");
+            for unmet_peer in unmet_peers {
+                warn!("  {unmet_peer}");
+            }
+        }
+
+        let mut deprecations = mem::take(&mut *utils::lock(&deprecations));
+        if !deprecations.is_empty() {
+            deprecations.sort();
+            warn!("Warning: deprecated packages were installed:");
+            for (package, message) in deprecations {
+                warn!("  {package}: {message}");
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Runs the `postinstall` scripts of the extracted packages, the dependencies of a package
+    /// before it. A package whose script fails is failed, so that it gets no lockfile and its
+    /// script is run again by the next install.
+    fn run_scripts(
+        dependency_map_mx: &DependencyMapMutex,
+        failures: &mut Vec<(String, CommandError)>,
+        script_policy: ScriptPolicy,
+    ) {
+        if script_policy == ScriptPolicy::Ignore {
+            return;
+        }
+
+        // a dependency has fewer dependencies than each of its dependents
+        let mut packages = utils::lock(dependency_map_mx)
+            .iter()
+            .filter(|(package, lock)| {
+                !failures
+                    .iter()
+                    .any(|(failed, _)| failed == *package || lock.dependencies.contains(failed))
+            })
+            .map(|(package, lock)| (lock.dependencies.len(), package.to_string()))
+            .collect::<Vec<_>>();
+        packages.sort();
+
+        let scripts = packages
+            .into_iter()
+            .filter_map(|(_, package)| {
+                let script = Installer::postinstall_script(&package)?;
+                Some((package, script))
+            })
+            .collect::<Vec<_>>();
+
+        if scripts.is_empty() {
+            return;
+        }
+
+        if script_policy == ScriptPolicy::Skip {
+            warn!("Warning: the postinstall scripts of these packages were not run, set PIE_RUN_SCRIPTS=true to run them or use --ignore-scripts to hide this warning:");
+            for (package, _) in scripts {
+                warn!("  {package}");
+            }
+            return;
+        }
+
+        for (package, script) in scripts {
+            info!("Running the postinstall script of '{package}': {script}");
+            if let Err(e) = Installer::run_script(&package, &script) {
+                failures.push((package, e));
+            }
+        }
+    }
+
+    /// Drops the packages that failed to install, and the ones depending on them, from the
+    /// dependency map so that no incomplete package gets a lockfile. Returns, for each
+    /// dropped package, the package that failed and why.
+    fn discard_failures(
+        dependency_map_mx: &DependencyMapMutex,
+        failures: Vec<(String, CommandError)>,
+    ) -> HashMap<String, (String, String)> {
+        let failures = failures
+            .into_iter()
+            .map(|(package, e)| (package, e.to_string()))
+            .collect::<HashMap<_, _>>();
+        let mut dependency_map = utils::lock(dependency_map_mx);
+
+        let broken = dependency_map
+            .iter()
+            .filter_map(|(package, lock)| {
+                let (failed, reason) = [package]
+                    .into_iter()
+                    .chain(lock.dependencies.iter())
+                    .find_map(|failed| Some((failed, failures.get(failed)?)))?;
+                Some((
+                    package.to_string(),
+                    (failed.to_string(), reason.to_string()),
+                ))
+            })
+            .collect::<HashMap<_, _>>();
+
+        for package in broken.keys() {
+            dependency_map.remove(package);
+        }
+
+        broken
+    }
+
+    /// Pins every package to its locked version, the one the project depends on when it still
+    /// matches. A frozen install fails if the lockfile has none matching, otherwise the
+    /// package is resolved again, like the ones not from the registry.
+    fn locked_packages(
+        packages: Vec<PackageDetails>,
+        project_lock: &ProjectLock,
+        locked_versions: &LockedVersions,
+        frozen: bool,
+    ) -> Result<Vec<PackageDetails>, CommandError> {
+        packages
+            .into_iter()
+            .map(|(package_name, package_version)| {
+                // a local directory isn't locked, it is linked as it is
+                let lockable = match package_version {
+                    VersionSpec::Local(_) => false,
+                    VersionSpec::Tarball(_) => frozen,
+                    VersionSpec::Range(_) | VersionSpec::Tag(_) => true,
+                };
+                if !lockable {
+                    return Ok((package_name, package_version));
+                }
+
+                let range = package_version.range();
+                let locked_version = project_lock
+                    .dependencies
+                    .get(&package_name)
+                    .filter(|version| {
+                        Version::parse(version)
+                            .is_ok_and(|version| range.is_none_or(|range| range.matches(&version)))
+                    })
+                    .cloned()
+                    .or_else(|| ProjectLock::locked_version(locked_versions, &package_name, range));
+                match (locked_version, frozen) {
+                    (Some(locked_version), _) => {
+                        Ok((package_name, Versions::exact_spec(&locked_version)))
+                    }
+                    (None, true) => Err(CommandError::LockfileOutOfDate(
+                        package_name.to_string(),
+                        package_version.to_string(),
+                    )),
+                    (None, false) => Ok((package_name, package_version)),
+                }
+            })
+            .collect()
+    }
+
+    /// The packages of the workspaces listed by the package.json of a monorepo.
+    fn read_workspaces(package_json: &PackageJson) -> Result<Workspaces, CommandError> {
+        package_json
+            .workspace_dirs()?
+            .into_iter()
+            .map(|workspace| {
+                let missing = || CommandError::MissingWorkspace(workspace.display().to_string());
+                let directory = fs::canonicalize(&workspace).map_err(|_| missing())?;
+                let workspace_json = PackageJson::read_from(&directory.join("package.json"))
+                    .map_err(|e| match e {
+                        CommandError::NoPackageJson(_) => missing(),
+                        e => e,
+                    })?;
+
+                let name = workspace_json.name.ok_or(CommandError::MissingPackageName(
+                    workspace.display().to_string(),
+                ))?;
+                let version = workspace_json.version.unwrap_or(String::from(UNVERSIONED));
+                Ok((name, (directory, version)))
+            })
+            .collect()
+    }
+
+    /// A dependency on a workspace whose version satisfies its range is linked to the
+    /// workspace, rather than fetched from the registry.
+    fn link_workspace(workspaces: &Workspaces, package: PackageDetails) -> PackageDetails {
+        let (name, spec) = package;
+        let Some((directory, version)) = workspaces.get(&name) else {
+            return (name, spec);
+        };
+
+        let satisfied = match &spec {
+            VersionSpec::Range(range) => Version::parse(version).is_ok_and(|v| range.matches(&v)),
+            VersionSpec::Tag(_) => true,
+            VersionSpec::Tarball(_) | VersionSpec::Local(_) => false,
+        };
+        match satisfied {
+            true => (
+                name,
+                VersionSpec::Local(directory.to_string_lossy().to_string()),
+            ),
+            false => (name, spec),
+        }
+    }
+
+    /// Links a local directory into `./node_modules` unless it is a dry run, and returns its
+    /// name, version and dependencies, its `devDependencies` too unless `production` is set.
+    /// The relative paths of its dependencies are resolved from the directory. A directory
+    /// that is already linked is skipped, so that local packages can depend on each other.
+    fn link_directory(
+        path: &Path,
+        linked_directories: &mut HashSet<PathBuf>,
+        production: bool,
+        dry_run: bool,
+    ) -> Result<Option<(String, String, Vec<PackageDetails>)>, CommandError> {
+        let directory = fs::canonicalize(path).map_err(CommandError::FailedToReadFile)?;
+        if !linked_directories.insert(directory.to_path_buf()) {
+            return Ok(None);
+        }
+
+        let package_json = PackageJson::read_from(&directory.join("package.json"))?;
+        let name = package_json
+            .name
+            .clone()
+            .ok_or(CommandError::MissingPackageName(path.display().to_string()))?;
+        let version = package_json
+            .version
+            .clone()
+            .unwrap_or(String::from(UNVERSIONED));
+
+        let dependencies = package_json
+            .all_dependencies(production)?
+            .into_iter()
+            .map(|(dependency, spec)| match spec {
+                VersionSpec::Local(local) => {
+                    let local = directory.join(local).to_string_lossy().to_string();
+                    (dependency, VersionSpec::Local(local))
+                }
+                spec => (dependency, spec),
+            })
+            .collect();
+
+        if dry_run {
+            return Ok(Some((name, version, dependencies)));
+        }
+
+        if let Some((scope, _)) = name.split_once('/') {
+            fs::create_dir_all(format!("./node_modules/{}", scope))
+                .map_err(CommandError::FailedToCreateDir)?;
+        }
+
+        let link = format!("./node_modules/{}", name);
+        if fs::symlink_metadata(&link).is_ok() {
+            utils::unlink_dir(Path::new(&link)).map_err(CommandError::FailedToRemoveFile)?;
+        }
+        utils::link_dir(&directory, Path::new(&link)).map_err(CommandError::FailedToCreateFile)?;
+
+        info!("Linked '{}' to '{}'", name, directory.display());
+        Ok(Some((name, version, dependencies)))
+    }
+
+    fn link_global_bins(package_name: &String, version: &String) -> Result<(), CommandError> {
+        let bin_dir = Path::new(GLOBAL_DIR.as_str()).join("bin");
+        let commands = Cache::link_bins(&Versions::stringify(package_name, version), &bin_dir)?;
+
+        for command in commands.iter() {
+            info!("Linked '{}' into '{}'", command, bin_dir.display());
+        }
 
-        for (package_name, lock) in dependency_map.iter() {
-            let path = format!("{}/{}/package/", *CACHE_DIR, package_name);
-            fs::create_dir_all(path.clone()).map_err(CommandError::FailedToCreateDir)?;
-            let mut file = File::create(format!("{path}/pie-lock.json"))
-                .map_err(CommandError::FailedToCreateFile)?;
-            let lock =
-                serde_json::to_string(lock).map_err(CommandError::FailedToSerializePackageLock)?;
-            file.write_all(lock.as_bytes())
-                .map_err(CommandError::FailedToWriteFile)?;
+        let on_path = env::var_os("PATH")
+            .is_some_and(|path| env::split_paths(&path).any(|dir| dir == bin_dir));
+        if !commands.is_empty() && !on_path {
+            info!("Add '{}' to your PATH to run them", bin_dir.display());
         }
 
         Ok(())
     }
+
+    fn report(
+        results: &[(String, Result<String, CommandError>)],
+        sources: &HashMap<String, Source>,
+        downloaded: &HashSet<String>,
+    ) -> Result<InstallReport, CommandError> {
+        let mut report = InstallReport {
+            installed: Vec::new(),
+            failed: Vec::new(),
+            packages: BTreeMap::new(),
+        };
+
+        let mut installed = Vec::new();
+        for (package_name, result) in results {
+            match result {
+                Ok(version) => {
+                    let source = sources.get(package_name).copied().unwrap_or(Source::Cache);
+                    // a local directory isn't cached, there is no lockfile to read its tree from
+                    if source != Source::Local {
+                        installed.push(Versions::stringify(package_name, version));
+                    }
+                    report.installed.push(InstalledPackage {
+                        name: package_name.to_string(),
+                        version: version.to_string(),
+                        source,
+                    });
+                }
+                Err(e) => report.failed.push(FailedPackage {
+                    name: package_name.to_string(),
+                    error: e.to_string(),
+                }),
+            }
+        }
+
+        for (package, dependencies) in ListHandler::build_graph(&installed)? {
+            let source = match downloaded.contains(&package) {
+                true => Source::Network,
+                false => Source::Cache,
+            };
+            report.packages.insert(
+                package,
+                ResolvedPackage {
+                    source,
+                    dependencies,
+                },
+            );
+        }
+
+        Ok(report)
+    }
+
+    /// Lists the packages a dry run would download, and the ones it would take from the cache.
+    fn print_plan(
+        dependency_map: &DependencyMap,
+        results: &[(String, Result<String, CommandError>)],
+        sources: &HashMap<String, Source>,
+    ) {
+        let mut downloaded = dependency_map.keys().cloned().collect::<Vec<_>>();
+        downloaded.sort();
+
+        // a cached dependency is only recorded in the locks of the packages depending on it
+        let requested_from_cache = results.iter().filter_map(|(package_name, result)| {
+            let version = result.as_ref().ok()?;
+            (sources.get(package_name) == Some(&Source::Cache))
+                .then(|| Versions::stringify(package_name, version))
+        });
+        let mut cached = dependency_map
+            .values()
+            .flat_map(|lock| lock.dependencies.iter().cloned())
+            .chain(requested_from_cache)
+            .filter(|package| !dependency_map.contains_key(package))
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect::<Vec<_>>();
+        cached.sort();
+
+        info!("Dry run, nothing was installed");
+        info!("Would download {} package(s):", downloaded.len());
+        for package in downloaded {
+            info!("  {package}");
+        }
+        info!("Would use {} package(s) from the cache:", cached.len());
+        for package in cached {
+            info!("  {package}");
+        }
+    }
+
+    /// The packages whose dependents asked for ranges that none of their resolved versions
+    /// satisfies at once, with each dependent, its range and the version it got.
+    fn range_conflicts(project_lock: &ProjectLock) -> BTreeMap<String, Vec<[String; 3]>> {
+        let mut requests: BTreeMap<String, Vec<[String; 3]>> = BTreeMap::new();
+        for (package, locked_package) in project_lock.packages.iter() {
+            for (name, requested) in locked_package.requested.iter() {
+                requests.entry(name.to_string()).or_default().push([
+                    package.to_string(),
+                    requested.range.to_string(),
+                    requested.version.to_string(),
+                ]);
+            }
+        }
+
+        if let Ok(package_json) = PackageJson::read() {
+            let declared = package_json
+                .dependencies
+                .iter()
+                .chain(package_json.dev_dependencies.iter());
+            for (name, range) in declared {
+                if let Some(version) = project_lock.dependencies.get(name) {
+                    requests.entry(name.to_string()).or_default().push([
+                        String::from(PACKAGE_JSON),
+                        range.to_string(),
+                        version.to_string(),
+                    ]);
+                }
+            }
+        }
+
+        // a dist-tag or a URL isn't a range that can conflict
+        requests.retain(|_, requests| {
+            let Ok(ranges) = requests
+                .iter()
+                .map(|[_, range, _]| Versions::parse_semantic_version(range))
+                .collect::<Result<Vec<_>, _>>()
+            else {
+                return false;
+            };
+
+            let is_satisfiable = requests
+                .iter()
+                .filter_map(|[_, _, version]| Version::parse(version).ok())
+                .any(|version| ranges.iter().all(|range| range.matches(&version)));
+            !is_satisfiable
+        });
+        requests
+    }
+
+    fn set_result(
+        results: &mut [(String, Result<String, CommandError>)],
+        package_name: &String,
+        result: Result<String, CommandError>,
+    ) {
+        if let Some((_, r)) = results.iter_mut().find(|(name, _)| name == package_name) {
+            *r = result;
+        }
+    }
 }
 
 #[async_trait]
 impl CommandHandler for InstallHandler {
     fn parse(&mut self, args: &mut Args) -> Result<(), ParseError> {
-        let package = args
-            .next()
-            .ok_or(ParseError::MissingArgument(String::from("package_name")))?;
+        // without package arguments, the dependencies of package.json are installed
+        for arg in args {
+            match arg.as_str() {
+                "--no-save" => self.no_save = true,
+                "--save-dev" | "-D" => self.save_dev = true,
+                "--save-exact" | "-E" => self.save_exact = true,
+                "--production" | "--omit=dev" => self.production = true,
+                "--global" | "-g" => self.global = true,
+                "--frozen-lockfile" => self.frozen_lockfile = true,
+                "--strict-peer" => self.strict_peer = true,
+                "--ignore-scripts" => self.ignore_scripts = true,
+                "--dry-run" => self.dry_run = true,
+                "--copy" => self.copy = true,
+                "--prefer-lowest" => self.preference = Preference::Lowest,
+                "--offline" | "--prefer-offline" | "--force" | "-f" => {
+                    let cache_mode = match arg.as_str() {
+                        "--offline" => CacheMode::Offline,
+                        "--prefer-offline" => CacheMode::PreferOffline,
+                        _ => CacheMode::Refresh,
+                    };
+                    if let (Some(flag), true) =
+                        (self.cache_mode.flag(), self.cache_mode != cache_mode)
+                    {
+                        return Err(ParseError::ConflictingArguments(flag.to_string(), arg));
+                    }
+                    self.cache_mode = cache_mode;
+                }
+                _ if arg.starts_with('-') => return Err(ParseError::InvalidArgument(arg)),
+                _ => {
+                    let (name, spec) = Versions::parse_semantic_package_details(arg)?;
+                    // tarballs and local packages are named by their package.json
+                    if let VersionSpec::Range(_) | VersionSpec::Tag(_) = spec {
+                        Versions::validate_package_name(&name)?;
+                    }
+                    self.packages.push((name, spec));
+                }
+            }
+        }
 
-        let (package_name, package_version) = Versions::parse_semantic_package_details(package)?;
-        self.package_name = package_name;
-        self.package_version = package_version;
+        // dev dependencies are never installed in production, saving one makes no sense
+        if self.production && self.save_dev {
+            return Err(ParseError::ConflictingArguments(
+                String::from("--production"),
+                String::from("--save-dev"),
+            ));
+        }
+
+        // the cache can't tell which versions are the lowest published ones
+        let relies_on_cache = matches!(
+            self.cache_mode,
+            CacheMode::PreferOffline | CacheMode::Offline
+        );
+        if let (Preference::Lowest, true, Some(flag)) =
+            (self.preference, relies_on_cache, self.cache_mode.flag())
+        {
+            return Err(ParseError::ConflictingArguments(
+                String::from("--prefer-lowest"),
+                flag.to_string(),
+            ));
+        }
+
+        // a frozen install only ever installs what package.json and the lockfile agree on
+        if let (true, Some((package_name, _))) = (self.frozen_lockfile, self.packages.first()) {
+            return Err(ParseError::ConflictingArguments(
+                String::from("--frozen-lockfile"),
+                package_name.to_string(),
+            ));
+        }
+
+        if self.global && self.packages.is_empty() {
+            return Err(ParseError::MissingArgument(String::from("package_name")));
+        }
 
         Ok(())
     }
 
     async fn execute(&self) -> Result<(), CommandError> {
-        println!("Installing '{}' ...", self.package_name);
-        let client = Client::new();
+        if self.copy {
+            Cache::set_link_strategy(LinkStrategy::Copy);
+        }
 
-        let semantic_version_ref = self.package_version.as_ref();
-        let full_version = Versions::resolve_full_version(semantic_version_ref);
-        let full_version_ref = full_version.as_ref();
-        let (is_cached, cached_version) =
-            Cache::exists(&self.package_name, full_version_ref, semantic_version_ref).await?;
+        // the global directory is laid out like a project, so the install simply runs from it
+        if self.global {
+            fs::create_dir_all(GLOBAL_DIR.as_str()).map_err(CommandError::FailedToCreateDir)?;
+            env::set_current_dir(GLOBAL_DIR.as_str()).map_err(CommandError::FailedToChangeDir)?;
+        }
 
-        utils::create_node_modules_dir();
+        // a broken package.json fails the install before anything is installed, rather than
+        // when the packages are saved to it, and only installing its dependencies needs one
+        let package_json = match PackageJson::read() {
+            Ok(package_json) => Some(package_json),
+            Err(CommandError::NoPackageJson(_)) if !self.packages.is_empty() => None,
+            Err(e) => return Err(e),
+        };
+        let workspaces = match &package_json {
+            Some(package_json) if !self.global => Self::read_workspaces(package_json)?,
+            _ => Workspaces::new(),
+        };
 
-        if is_cached {
-            let version = cached_version.expect("Failed to get cached version");
-            Cache::load_cached_version(Versions::stringify(&self.package_name, &version));
-            return Ok(());
-        }
-
-        let version_data = Installer::get_version_data(
-            client.clone(),
-            &self.package_name,
-            full_version_ref,
-            semantic_version_ref,
-        )
-        .await?;
-
-        let (sender, receiver) = channel::<PackageBytes>();
-
-        // TODO: find a better way to handle this
-        // forced to use this to make sure that at least one task is received
-        // if not, the program might exit before the task is received
-        // which ends up in caching a package without the actual code
-        let task_received = Arc::new(AtomicBool::new(false));
-        TaskAllocator::add_blocking_task(move || {
-            let task_received = Arc::clone(&task_received);
-            println!("Starting extraction task...");
-            while !task_received.load(std::sync::atomic::Ordering::Relaxed) {
-                while let Ok((package_dest, bytes)) = receiver.recv() {
-                    task_received.store(true, std::sync::atomic::Ordering::Relaxed);
-                    println!("Extracting package to '{}'", package_dest);
-                    utils::extract_tarball(bytes, package_dest).unwrap()
+        let mut packages = match (&package_json, self.packages.is_empty()) {
+            (Some(package_json), true) => package_json.all_dependencies(self.production)?,
+            _ => self.packages.clone(),
+        };
+        // at the root of a monorepo, every workspace is installed along with the project
+        if self.packages.is_empty() {
+            packages.extend(workspaces.iter().map(|(name, (directory, _))| {
+                let directory = directory.to_string_lossy().to_string();
+                (name.to_string(), VersionSpec::Local(directory))
+            }));
+        }
+        let mut packages = packages
+            .into_iter()
+            .map(|package| Self::link_workspace(&workspaces, package))
+            .collect::<Vec<_>>();
+
+        // the versions of an existing lockfile are kept as long as package.json allows them,
+        // unless the lowest ones are asked for
+        let project_lock = match (self.frozen_lockfile, self.preference) {
+            (true, _) => Some(ProjectLock::read()?),
+            (false, Preference::Highest) => ProjectLock::read().ok(),
+            (false, Preference::Lowest) => None,
+        };
+        let locked_versions = project_lock
+            .as_ref()
+            .map(|project_lock| Arc::new(project_lock.locked_versions()));
+
+        // the packages given on the command line are resolved again, like npm does
+        if let (Some(project_lock), Some(locked_versions), true) =
+            (&project_lock, &locked_versions, self.packages.is_empty())
+        {
+            packages = Self::locked_packages(
+                packages,
+                project_lock,
+                locked_versions,
+                self.frozen_lockfile,
+            )?;
+        }
+
+        let (mut install_context, receiver) =
+            InstallContext::new(locked_versions, self.cache_mode, self.dry_run);
+        install_context.frozen = self.frozen_lockfile;
+        if self.ignore_scripts {
+            install_context.script_policy = ScriptPolicy::Ignore;
+        }
+        install_context.preference = self.preference;
+        if let Some(package_json) = &package_json {
+            install_context.overrides = Arc::new(package_json.overrides());
+        }
+        if let (Some(project_lock), true) = (project_lock, self.frozen_lockfile) {
+            let integrities = project_lock
+                .packages
+                .into_iter()
+                .filter_map(|(package, locked_package)| Some((package, locked_package.integrity?)))
+                .collect();
+            install_context.locked_integrities = Some(Arc::new(integrities));
+        }
+        if !self.dry_run {
+            utils::create_node_modules_dir();
+        }
+
+        let mut results = Vec::new();
+        let mut sources = HashMap::new();
+        let mut saved_specs = HashMap::new();
+
+        // only the requested packages are saved, not the dependencies of local directories
+        let mut saved = HashSet::new();
+
+        // a local directory is linked as it is, and its own dependencies installed along the
+        // other packages
+        let mut linked_directories = HashSet::new();
+        let mut pending = packages
+            .into_iter()
+            .map(|package| (package, true))
+            .collect::<VecDeque<_>>();
+        let mut packages = Vec::new();
+        while let Some((package, save)) = pending.pop_front() {
+            let (package_name, package_version) = Self::link_workspace(&workspaces, package);
+            let path = match &package_version {
+                VersionSpec::Local(path) if Path::new(path).is_dir() => PathBuf::from(path),
+                _ => {
+                    packages.push((package_name, package_version, save));
+                    continue;
                 }
+            };
+
+            // the workspaces are developed in the monorepo, unlike the other local packages
+            let production =
+                self.production || !workspaces.values().any(|(directory, _)| *directory == path);
+            match Self::link_directory(&path, &mut linked_directories, production, self.dry_run) {
+                Ok(Some((name, version, dependencies))) => {
+                    if save {
+                        saved.insert(name.to_string());
+                    }
+                    // a workspace is saved with its version, like a registry package
+                    if !workspaces.contains_key(&name) {
+                        saved_specs.insert(name.to_string(), package_version.to_string());
+                    }
+                    pending.extend(
+                        dependencies
+                            .into_iter()
+                            .map(|dependency| (dependency, false)),
+                    );
+                    sources.insert(name.to_string(), Source::Local);
+                    results.push((name, Ok(version)));
+                }
+                Ok(None) => {}
+                Err(e) => results.push((package_name, Err(e))),
             }
-        });
+        }
 
-        let dependency_map_mutex = Arc::new(Mutex::new(HashMap::new()));
+        let resolution_tasks = packages
+            .into_iter()
+            .map(|(package_name, package_version, save)| {
+                let install_context = install_context.clone();
+                TaskAllocator::add_task(async move {
+                    let resolution =
+                        Self::resolve(&install_context, &package_name, &package_version).await;
+                    (package_name, package_version, save, resolution)
+                })
+            })
+            .collect::<Vec<_>>();
 
-        let install_context = InstallContext {
-            client,
-            sender,
-            dependency_map_mx: Arc::clone(&dependency_map_mutex),
-        };
+        let mut fetched = Vec::new();
+        for task in resolution_tasks {
+            let (package_name, package_version, save, resolution) =
+                task.await.expect("Failed to join resolution task");
 
-        let stringified = Versions::stringify(&version_data.name, &version_data.version);
-        let package_info = PackageInfo {
-            version_data,
-            is_latest: Versions::is_latest(full_version_ref),
-            stringified: stringified.clone(),
+            // a tarball is only named once read, and is saved with its URL or path
+            let package_name = match (&package_version, &resolution) {
+                (
+                    VersionSpec::Tarball(_) | VersionSpec::Local(_),
+                    Ok(Resolution::Fetched(package_info)),
+                ) => {
+                    let name = package_info.version_data.name.to_string();
+                    saved_specs.insert(name.to_string(), package_version.to_string());
+                    name
+                }
+                _ => package_name,
+            };
+
+            if save {
+                saved.insert(package_name.to_string());
+            }
+
+            match resolution {
+                Ok(Resolution::Cached(version)) => {
+                    let stringified = Versions::stringify(&package_name, &version);
+                    let cached_dependencies = Cache::read_lock(&stringified)
+                        .map(|lock| lock.dependencies)
+                        .unwrap_or_default();
+                    install_context
+                        .metrics
+                        .add_cached(cached_dependencies.into_iter().chain([stringified.clone()]));
+                    let linked = match self.dry_run {
+                        true => Ok(()),
+                        false => Cache::load_cached_version(stringified),
+                    };
+                    sources.insert(package_name.to_string(), Source::Cache);
+                    results.push((package_name, linked.map(|_| version)));
+                }
+                Ok(Resolution::Fetched(package_info)) => {
+                    results.push((
+                        package_name.to_string(),
+                        Ok(package_info.version_data.version.to_string()),
+                    ));
+                    sources.insert(package_name.to_string(), Source::Network);
+                    fetched.push((package_name, *package_info));
+                }
+                Err(e) => results.push((package_name, Err(e))),
+            }
+        }
+
+        install_context.metrics.resolution_done();
+
+        // once the install is done, the dependency map only holds the downloaded packages
+        let dependency_map_mx = Arc::clone(&install_context.dependency_map_mx);
+        let metrics = Arc::clone(&install_context.metrics);
+        if !fetched.is_empty() {
+            Self::install(install_context, receiver, fetched, &mut results)?;
+        }
+
+        if self.dry_run {
+            Self::print_plan(&utils::lock(&dependency_map_mx), &results, &sources);
+        }
+
+        let mut failures = 0;
+        for (package_name, result) in results.iter() {
+            match result {
+                Ok(_) if self.dry_run => {}
+                Ok(version) => {
+                    info!("Package '{}' installed successfully!", package_name);
+
+                    if self.global {
+                        Self::link_global_bins(package_name, version)?;
+                    } else if !self.packages.is_empty()
+                        && !self.no_save
+                        && saved.contains(package_name)
+                    {
+                        let spec = saved_specs.get(package_name).cloned().unwrap_or(
+                            match self.save_exact {
+                                true => version.to_string(),
+                                false => format!("^{version}"),
+                            },
+                        );
+                        PackageJson::save_dependency(package_name, &spec, self.save_dev)?;
+                    }
+                }
+                Err(e) => {
+                    error!("Package '{}' failed to install: {e}", package_name);
+                    failures += 1;
+                }
+            }
+        }
+
+        let project_lock = match self.dry_run {
+            true => None,
+            false => Some(ProjectLock::collect()?),
         };
+        if let (Some(project_lock), false) = (&project_lock, self.frozen_lockfile) {
+            debug!("Writing project lockfile...");
+            project_lock.write()?;
+        }
+
+        if let (Some(max_size), false) = (*CACHE_MAX, self.dry_run) {
+            let (evicted, reclaimed) = Cache::gc(max_size)?;
+            if !evicted.is_empty() {
+                info!(
+                    "Evicted {} package(s) from the cache, {} reclaimed",
+                    evicted.len(),
+                    utils::format_size(reclaimed)
+                );
+            }
+        }
+
+        if Reporter::is_json() && !self.dry_run {
+            let downloaded = utils::lock(&dependency_map_mx).keys().cloned().collect();
+            Reporter::print(&Self::report(&results, &sources, &downloaded)?)?;
+        }
+
+        if !self.dry_run {
+            info!("{}", metrics.summary());
+        }
+
+        if failures > 0 {
+            return Err(CommandError::PackagesFailed(failures));
+        }
 
-        println!("Installing the package");
-        Installer::install_package(
-            install_context,
-            package_info,
-            Arc::new(Mutex::new(Vec::new())),
-        )?;
-        TaskAllocator::block_until_done();
-        println!("All tasks are done!");
+        if let (Some(project_lock), true) = (&project_lock, self.strict_peer) {
+            let conflicts = Self::range_conflicts(project_lock);
+            for (name, requests) in conflicts.iter() {
+                error!("Conflicting ranges for '{name}':");
+                for [dependent, range, version] in requests {
+                    error!("  {range} from {dependent}, resolved to {version}");
+                }
+            }
 
-        println!("Writing lockfiles...");
-        Self::write_lockfiles(dependency_map_mutex)?;
-        Cache::load_cached_version(stringified);
+            if !conflicts.is_empty() {
+                return Err(CommandError::ConflictingRanges(conflicts.len()));
+            }
+        }
 
-        println!("Package '{}' installed successfully!", self.package_name);
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<InstallHandler, ParseError> {
+        let mut handler = InstallHandler::default();
+        let args = args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+        handler.parse(&mut args.into_iter())?;
+        Ok(handler)
+    }
+
+    #[test]
+    fn saves_dev_dependencies_whatever_the_flag_position() {
+        for args in [
+            ["--save-dev", "express"],
+            ["express", "--save-dev"],
+            ["-D", "express"],
+            ["express", "-D"],
+        ] {
+            let handler = parse(&args).unwrap();
+            assert!(handler.save_dev, "{args:?}");
+            assert_eq!(handler.packages[0].0, "express");
+        }
+
+        assert!(!parse(&["express"]).unwrap().save_dev);
+    }
+
+    #[test]
+    fn rejects_saving_dev_dependencies_in_production() {
+        let parsed = parse(&["--production", "-D", "express"]);
+        assert!(matches!(parsed, Err(ParseError::ConflictingArguments(..))));
+    }
+}
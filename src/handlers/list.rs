@@ -0,0 +1,204 @@
+use crate::cache::Cache;
+use crate::command_handler::{Args, CommandHandler};
+use crate::errors::{CommandError, ParseError};
+use crate::report::{Reporter, TreeNode};
+use crate::versions::Versions;
+use async_trait::async_trait;
+use std::collections::{HashMap, HashSet};
+
+type DependencyGraph = HashMap<String, Vec<String>>;
+
+#[derive(Default)]
+pub struct ListHandler {
+    depth: Option<usize>,
+}
+
+impl ListHandler {
+    /// Builds the direct dependencies of every package from the lockfiles.
+    /// A lockfile lists every transitive dependency of its package, so the ones
+    /// reachable through another dependency are dropped to recover the nesting.
+    pub fn build_graph(packages: &[String]) -> Result<DependencyGraph, CommandError> {
+        let mut closures: HashMap<String, HashSet<String>> = HashMap::new();
+        let mut pending = packages.to_vec();
+
+        while let Some(package) = pending.pop() {
+            if closures.contains_key(&package) {
+                continue;
+            }
+
+            let lock = Cache::read_lock(&package)?;
+            pending.extend(lock.dependencies.iter().cloned());
+            closures.insert(package, lock.dependencies.into_iter().collect());
+        }
+
+        let mut graph = HashMap::new();
+        for (package, closure) in closures.iter() {
+            let mut direct = closure
+                .iter()
+                .filter(|dependency| {
+                    !closure.iter().any(|other| {
+                        other != *dependency
+                            && closures
+                                .get(other)
+                                .is_some_and(|other_closure| other_closure.contains(*dependency))
+                    })
+                })
+                .cloned()
+                .collect::<Vec<_>>();
+            direct.sort();
+
+            graph.insert(package.to_string(), direct);
+        }
+
+        Ok(graph)
+    }
+
+    fn reachable(packages: &[String], graph: &DependencyGraph) -> HashSet<String> {
+        let mut reachable = HashSet::new();
+        let mut pending = packages.to_vec();
+
+        while let Some(package) = pending.pop() {
+            if reachable.insert(package.to_string()) {
+                pending.extend(graph.get(&package).into_iter().flatten().cloned());
+            }
+        }
+
+        reachable
+    }
+
+    fn tree_nodes(
+        &self,
+        package: &String,
+        graph: &DependencyGraph,
+        printed: &mut HashSet<String>,
+        depth: usize,
+    ) -> Vec<TreeNode> {
+        if self.depth.is_some_and(|max_depth| depth > max_depth) {
+            return Vec::new();
+        }
+
+        let dependencies = graph.get(package).cloned().unwrap_or_default();
+        dependencies
+            .iter()
+            .map(|dependency| {
+                let (name, version) = Versions::parse_raw_package_details(dependency.to_string());
+                let has_children = graph.get(dependency).is_some_and(|d| !d.is_empty());
+                let deduped = has_children && !printed.insert(dependency.to_string());
+                let dependencies = match deduped {
+                    true => Vec::new(),
+                    false => self.tree_nodes(dependency, graph, printed, depth + 1),
+                };
+
+                TreeNode {
+                    name,
+                    version,
+                    deduped,
+                    dependencies,
+                }
+            })
+            .collect()
+    }
+
+    fn print_tree(
+        &self,
+        package: &String,
+        graph: &DependencyGraph,
+        printed: &mut HashSet<String>,
+        prefix: &str,
+        depth: usize,
+    ) {
+        if self.depth.is_some_and(|max_depth| depth > max_depth) {
+            return;
+        }
+
+        let dependencies = graph.get(package).cloned().unwrap_or_default();
+        for (i, dependency) in dependencies.iter().enumerate() {
+            let is_last = i == dependencies.len() - 1;
+            let (branch, indent) = if is_last {
+                ("└── ", "    ")
+            } else {
+                ("├── ", "│   ")
+            };
+
+            let has_children = graph.get(dependency).is_some_and(|d| !d.is_empty());
+            if has_children && !printed.insert(dependency.to_string()) {
+                println!("{prefix}{branch}{dependency} (deduped)");
+                continue;
+            }
+
+            println!("{prefix}{branch}{dependency}");
+            self.print_tree(
+                dependency,
+                graph,
+                printed,
+                &format!("{prefix}{indent}"),
+                depth + 1,
+            );
+        }
+    }
+}
+
+#[async_trait]
+impl CommandHandler for ListHandler {
+    fn parse(&mut self, args: &mut Args) -> Result<(), ParseError> {
+        for arg in args {
+            match arg.strip_prefix("--depth=") {
+                Some(depth) => {
+                    let depth = depth
+                        .parse::<usize>()
+                        .map_err(|_| ParseError::InvalidArgument(arg.to_string()))?;
+                    self.depth = Some(depth);
+                }
+                None => return Err(ParseError::InvalidArgument(arg)),
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn execute(&self) -> Result<(), CommandError> {
+        let linked = Cache::linked_packages()?;
+
+        if linked.is_empty() && Reporter::is_json() {
+            return Reporter::print(&Vec::<TreeNode>::new());
+        }
+
+        if linked.is_empty() {
+            println!("No packages installed");
+            return Ok(());
+        }
+
+        let mut graph = Self::build_graph(&linked)?;
+
+        // every linked package that isn't required by another one was installed directly
+        let required = graph.values().flatten().cloned().collect::<HashSet<_>>();
+        let mut roots = linked
+            .iter()
+            .filter(|package| !required.contains(*package))
+            .cloned()
+            .collect::<Vec<_>>();
+        roots.sort();
+
+        // packages only required from within a dependency cycle can't be reached from the roots
+        let mut reachable = Self::reachable(&roots, &graph);
+        let mut linked = linked;
+        linked.sort();
+        for package in linked {
+            if !reachable.contains(&package) {
+                reachable.extend(Self::reachable(&[package.to_string()], &graph));
+                roots.push(package);
+            }
+        }
+
+        let root = String::from(".");
+        graph.insert(root.to_string(), roots);
+
+        if Reporter::is_json() {
+            return Reporter::print(&self.tree_nodes(&root, &graph, &mut HashSet::new(), 0));
+        }
+
+        println!("{root}");
+        self.print_tree(&root, &graph, &mut HashSet::new(), "", 0);
+        Ok(())
+    }
+}
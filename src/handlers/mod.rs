@@ -1 +1,14 @@
+pub mod audit;
+pub mod cache;
+pub mod check;
+pub mod dedupe;
+pub mod exec;
+pub mod graph;
+pub mod info;
+pub mod init;
 pub mod install;
+pub mod list;
+pub mod outdated;
+pub mod search;
+pub mod uninstall;
+pub mod update;
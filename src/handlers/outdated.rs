@@ -0,0 +1,128 @@
+use crate::cache::Cache;
+use crate::command_handler::{Args, CommandHandler};
+use crate::errors::{CommandError, ParseError};
+use crate::http::HttpRequest;
+use crate::package_json::PackageJson;
+use crate::report::{OutdatedPackage, Reporter};
+use crate::types::{PackageData, ProjectLock};
+use crate::utils::{self, TaskAllocator, LATEST};
+use crate::versions::{Preference, VersionSpec, Versions};
+use async_trait::async_trait;
+use std::collections::HashMap;
+
+const MISSING: &str = "-";
+
+#[derive(Default)]
+pub struct OutdatedHandler;
+
+impl OutdatedHandler {
+    /// The installed version of every package, from the lockfile or else from `./node_modules`.
+    fn current_versions() -> Result<HashMap<String, String>, CommandError> {
+        if let Ok(project_lock) = ProjectLock::read() {
+            return Ok(project_lock.dependencies.into_iter().collect());
+        }
+
+        Ok(Cache::linked_packages()?
+            .into_iter()
+            .map(Versions::parse_raw_package_details)
+            .collect())
+    }
+
+    fn wanted_version(package_version: &VersionSpec, package_data: &PackageData) -> Option<String> {
+        match package_version {
+            VersionSpec::Tag(tag) => package_data.dist_tags.get(tag).cloned(),
+            VersionSpec::Tarball(_) | VersionSpec::Local(_) => None,
+            VersionSpec::Range(range) => {
+                Versions::resolve_partial_version(Some(range), package_data, Preference::Highest)
+                    .ok()
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl CommandHandler for OutdatedHandler {
+    fn parse(&mut self, args: &mut Args) -> Result<(), ParseError> {
+        match args.next() {
+            Some(arg) => Err(ParseError::InvalidArgument(arg)),
+            None => Ok(()),
+        }
+    }
+
+    async fn execute(&self) -> Result<(), CommandError> {
+        let mut packages = PackageJson::read()?.all_dependencies(false)?;
+        // a package installed from a tarball or a local path has no newer version to look for
+        packages.retain(|(_, package_version)| {
+            !matches!(
+                package_version,
+                VersionSpec::Tarball(_) | VersionSpec::Local(_)
+            )
+        });
+        let current_versions = Self::current_versions()?;
+        let client = HttpRequest::client();
+
+        // the package data gives both the wanted and the latest version, in one request
+        let tasks = packages
+            .into_iter()
+            .map(|(package_name, package_version)| {
+                let client = client.clone();
+                TaskAllocator::add_task(async move {
+                    let package_data = HttpRequest::package_data(client, &package_name).await;
+                    (package_name, package_version, package_data)
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let mut rows = Vec::new();
+        for task in tasks {
+            let (package_name, package_version, package_data) =
+                task.await.expect("Failed to join outdated task");
+            let package_data = package_data?;
+
+            let current = current_versions.get(&package_name).cloned();
+            let wanted = Self::wanted_version(&package_version, &package_data);
+            let latest = package_data.dist_tags.get(LATEST).cloned();
+
+            if current.is_some() && current == wanted && current == latest {
+                continue;
+            }
+
+            rows.push(OutdatedPackage {
+                package: package_name,
+                current,
+                wanted,
+                latest,
+            });
+        }
+        rows.sort_by(|a, b| a.package.cmp(&b.package));
+
+        if Reporter::is_json() {
+            return Reporter::print(&rows);
+        }
+
+        if rows.is_empty() {
+            println!("All packages are up to date");
+            return Ok(());
+        }
+
+        let or_missing = |version: Option<String>| version.unwrap_or(String::from(MISSING));
+        let mut rows = rows
+            .into_iter()
+            .map(|row| {
+                [
+                    row.package,
+                    or_missing(row.current),
+                    or_missing(row.wanted),
+                    or_missing(row.latest),
+                ]
+            })
+            .collect::<Vec<_>>();
+        rows.insert(
+            0,
+            ["package", "current", "wanted", "latest"].map(String::from),
+        );
+        utils::print_table(&rows);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,82 @@
+use crate::command_handler::{Args, CommandHandler};
+use crate::errors::{CommandError, ParseError};
+use crate::http::HttpRequest;
+use crate::utils;
+use async_trait::async_trait;
+
+/// The number of results of the registry when none is asked for.
+const DEFAULT_LIMIT: usize = 20;
+
+pub struct SearchHandler {
+    words: Vec<String>,
+    limit: usize,
+}
+
+impl Default for SearchHandler {
+    fn default() -> Self {
+        Self {
+            words: Vec::new(),
+            limit: DEFAULT_LIMIT,
+        }
+    }
+}
+
+#[async_trait]
+impl CommandHandler for SearchHandler {
+    fn parse(&mut self, args: &mut Args) -> Result<(), ParseError> {
+        for arg in args {
+            match arg.strip_prefix("--limit=") {
+                Some(limit) => {
+                    self.limit = limit
+                        .parse::<usize>()
+                        .ok()
+                        .filter(|limit| *limit > 0)
+                        .ok_or(ParseError::InvalidArgument(arg.to_string()))?;
+                }
+                None if arg.starts_with('-') => return Err(ParseError::InvalidArgument(arg)),
+                None => self.words.push(arg),
+            }
+        }
+
+        if self.words.is_empty() {
+            return Err(ParseError::MissingArgument(String::from("search_terms")));
+        }
+
+        Ok(())
+    }
+
+    async fn execute(&self) -> Result<(), CommandError> {
+        let text = self.words.join(" ");
+        let results = HttpRequest::search(HttpRequest::client(), &text, self.limit).await?;
+
+        if results.objects.is_empty() {
+            println!("No packages found for '{text}'");
+            return Ok(());
+        }
+
+        let shown = results.objects.len();
+        let mut rows = results
+            .objects
+            .into_iter()
+            .map(|result| {
+                let package = result.package;
+                [
+                    package.name,
+                    package.version,
+                    package.description.unwrap_or_default(),
+                ]
+            })
+            .collect::<Vec<_>>();
+        rows.insert(0, ["name", "version", "description"].map(String::from));
+        utils::print_table(&rows);
+
+        if results.total > shown as u64 {
+            println!(
+                "{shown} of {} packages shown, use --limit=N to see more",
+                results.total
+            );
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,57 @@
+use crate::command_handler::{Args, CommandHandler};
+use crate::errors::{CommandError, ParseError};
+use crate::types::ProjectLock;
+use crate::utils;
+use crate::versions::Versions;
+use async_trait::async_trait;
+use log::info;
+use std::fs;
+use std::path::Path;
+
+#[derive(Default)]
+pub struct UninstallHandler {
+    package_name: String,
+}
+
+#[async_trait]
+impl CommandHandler for UninstallHandler {
+    fn parse(&mut self, args: &mut Args) -> Result<(), ParseError> {
+        let package = args
+            .next()
+            .ok_or(ParseError::MissingArgument(String::from("package_name")))?;
+
+        let (package_name, _) = Versions::parse_raw_package_details(package);
+        self.package_name = package_name;
+
+        Ok(())
+    }
+
+    async fn execute(&self) -> Result<(), CommandError> {
+        let link = format!("./node_modules/{}", self.package_name);
+
+        if fs::symlink_metadata(&link).is_err() {
+            info!("Package '{}' is not installed", self.package_name);
+            return Ok(());
+        }
+
+        info!("Uninstalling '{}' ...", self.package_name);
+        utils::unlink_dir(Path::new(&link)).map_err(CommandError::FailedToRemoveFile)?;
+
+        // the cache entry is kept, only the project's link to it is removed
+        if let Some((scope, _)) = self.package_name.split_once('/') {
+            let scope_dir = format!("./node_modules/{}", scope);
+            let is_empty = fs::read_dir(&scope_dir)
+                .map(|mut entries| entries.next().is_none())
+                .unwrap_or(false);
+
+            if is_empty {
+                fs::remove_dir(&scope_dir).map_err(CommandError::FailedToRemoveFile)?;
+            }
+        }
+
+        ProjectLock::collect()?.write()?;
+
+        info!("Package '{}' uninstalled successfully!", self.package_name);
+        Ok(())
+    }
+}
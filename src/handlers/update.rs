@@ -0,0 +1,138 @@
+use crate::cache::Cache;
+use crate::command_handler::{Args, CommandHandler};
+use crate::errors::{CommandError, ParseError};
+use crate::handlers::install::InstallHandler;
+use crate::installer::{CacheMode, InstallContext, Installer, PackageInfo};
+use crate::package_json::PackageJson;
+use crate::types::ProjectLock;
+use crate::utils;
+use crate::versions::{VersionSpec, Versions};
+use async_trait::async_trait;
+use log::{error, info};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+#[derive(Default)]
+pub struct UpdateHandler {
+    package_name: Option<String>,
+}
+
+impl UpdateHandler {
+    fn unlink(package_name: &String) -> Result<(), CommandError> {
+        let link = format!("./node_modules/{}", package_name);
+
+        match fs::symlink_metadata(&link) {
+            Ok(_) => utils::unlink_dir(Path::new(&link)).map_err(CommandError::FailedToRemoveFile),
+            Err(_) => Ok(()),
+        }
+    }
+}
+
+#[async_trait]
+impl CommandHandler for UpdateHandler {
+    fn parse(&mut self, args: &mut Args) -> Result<(), ParseError> {
+        if let Some(package) = args.next() {
+            let (package_name, _) = Versions::parse_raw_package_details(package);
+            self.package_name = Some(package_name);
+        }
+
+        match args.next() {
+            Some(arg) => Err(ParseError::InvalidArgument(arg)),
+            None => Ok(()),
+        }
+    }
+
+    async fn execute(&self) -> Result<(), CommandError> {
+        let mut packages = PackageJson::read()?.all_dependencies(false)?;
+
+        if let Some(package_name) = &self.package_name {
+            packages.retain(|(name, _)| name == package_name);
+
+            if packages.is_empty() {
+                return Err(CommandError::NotADependency(package_name.to_string()));
+            }
+        }
+
+        let installed = Cache::linked_packages()?
+            .into_iter()
+            .map(Versions::parse_raw_package_details)
+            .collect::<HashMap<_, _>>();
+
+        let (install_context, receiver) = InstallContext::new(None, CacheMode::Default, false);
+        utils::create_node_modules_dir();
+
+        let mut results = Vec::new();
+        let mut fetched = Vec::new();
+        for (package_name, package_version) in packages {
+            // unlike `install`, the registry is always asked for the highest matching version
+            let tag = match &package_version {
+                VersionSpec::Tag(tag) => Some(tag),
+                VersionSpec::Range(_) => None,
+                VersionSpec::Tarball(_) | VersionSpec::Local(_) => {
+                    info!(
+                        "Package '{}' is installed from '{}'",
+                        package_name, package_version
+                    );
+                    continue;
+                }
+            };
+            let version_data = Installer::get_version_data(
+                &install_context,
+                &package_name,
+                tag,
+                package_version.range(),
+            )
+            .await?;
+
+            if installed.get(&package_name) == Some(&version_data.version) {
+                info!(
+                    "Package '{}' is already up to date ({})",
+                    package_name, version_data.version
+                );
+                continue;
+            }
+
+            Self::unlink(&package_name)?;
+            let version = version_data.version.to_string();
+
+            if Cache::is_in_cache(&package_name, &version) {
+                Cache::load_cached_version(Versions::stringify(&package_name, &version))?;
+            } else {
+                let package_info = PackageInfo {
+                    stringified: Versions::stringify(&package_name, &version),
+                    version_data,
+                    is_latest: false,
+                    optional: false,
+                    tarball: None,
+                };
+                fetched.push((package_name.to_string(), package_info));
+            }
+
+            results.push((package_name, Ok(version)));
+        }
+
+        if !fetched.is_empty() {
+            InstallHandler::install(install_context, receiver, fetched, &mut results)?;
+        }
+
+        let mut failures = 0;
+        for (package_name, result) in results.iter() {
+            match result {
+                Ok(version) => info!("Package '{}' updated to {}", package_name, version),
+                Err(e) => {
+                    error!("Package '{}' failed to update: {e}", package_name);
+                    failures += 1;
+                }
+            }
+        }
+
+        ProjectLock::collect()?.write()?;
+
+        if failures > 0 {
+            return Err(CommandError::PackagesFailed(failures));
+        }
+
+        Ok(())
+    }
+}
@@ -1,34 +1,375 @@
-use crate::errors::CommandError;
-use crate::types::{PackageData, VersionData};
+use crate::cache::Cache;
+use crate::config::CONFIG;
+use crate::errors::{CommandError, ParseError};
+use crate::npmrc::Npmrc;
+use crate::types::{Advisory, CachedMetadata, PackageData, SearchResults, VersionData};
+use crate::utils::{self, LATEST, REGISTRY_URL};
 use bytes::Bytes;
-use reqwest::Client;
+use lazy_static::lazy_static;
+use log::warn;
+use reqwest::header::{ACCEPT, CONTENT_TYPE, ETAG, IF_NONE_MATCH, RANGE};
+use reqwest::{Client, ClientBuilder, RequestBuilder, Response, StatusCode, Url};
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+use tokio::sync::OnceCell;
+
+const DEFAULT_RETRIES: u32 = 3;
+const BASE_BACKOFF_MS: u64 = 200;
+const DEFAULT_TIMEOUT_SECS: u64 = 30;
+const MAX_IDLE_CONNECTIONS: usize = 32;
+const TARBALL_TIMEOUT_FACTOR: u32 = 10;
+/// The abbreviated documents are much smaller, but some registries only have the full ones.
+const REGISTRY_ACCEPT: &str =
+    "application/vnd.npm.install-v1+json; q=1.0, application/json; q=0.8, */*";
+
+const ADVISORIES_ROUTE: &str = "-/npm/v1/security/advisories/bulk";
+
+static REGISTRY_FLAG: OnceLock<String> = OnceLock::new();
+
+lazy_static! {
+    static ref HTTP_RETRIES: u32 = CONFIG.http_retries.unwrap_or(DEFAULT_RETRIES);
+    static ref TIMEOUT: Duration =
+        Duration::from_secs(CONFIG.timeout.unwrap_or(DEFAULT_TIMEOUT_SECS));
+    static ref NPMRC: Npmrc = Npmrc::load();
+    static ref REGISTRY: String = HttpRequest::registry_url();
+    static ref AUTH_TOKEN: Option<String> = env::var("PIE_TOKEN")
+        .ok()
+        .or_else(|| NPMRC.auth_token(&REGISTRY).cloned());
+    static ref PACKAGE_DOCUMENTS: Mutex<HashMap<String, Arc<OnceCell<String>>>> =
+        Mutex::new(HashMap::new());
+}
+
+struct RegistryResponse {
+    status: StatusCode,
+    etag: Option<String>,
+    body: String,
+}
+
+impl RegistryResponse {
+    /// The body, unless the registry answered 404.
+    fn found(self) -> Option<String> {
+        match self.status {
+            StatusCode::NOT_FOUND => None,
+            _ => Some(self.body),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct Progress {
+    pub received: u64,
+    pub total: Option<u64>,
+}
+
+impl Progress {
+    pub fn percent(&self) -> Option<u64> {
+        self.total
+            .filter(|total| *total > 0)
+            .map(|total| (self.received * 100 / total).min(100))
+    }
+}
 
 pub struct HttpRequest;
 impl HttpRequest {
-    async fn registry(client: Client, route: String) -> Result<String, CommandError> {
-        client
-            .get(format!("{}/{}", crate::utils::REGISTRY_URL, route))
-            .header(
-                "Accept",
-                "application/vnd.npm.install-v1+json; q=1.0, application/json; q=0.8, */*",
-            )
-            .send()
-            .await
-            .map_err(CommandError::HTTPFailed)?
-            .text()
+    /// Its connections are pooled, so it should be shared by every request of a command.
+    pub fn client() -> Client {
+        ClientBuilder::new()
+            .connect_timeout(*TIMEOUT)
+            .pool_max_idle_per_host(MAX_IDLE_CONNECTIONS)
+            .build()
+            .expect("Failed to build the HTTP client")
+    }
+
+    fn http_error(url: &str, e: reqwest::Error) -> CommandError {
+        match e.is_timeout() {
+            true => CommandError::Timeout(url.to_string()),
+            false => CommandError::HTTPFailed(e),
+        }
+    }
+
+    /// Only takes effect before the first request.
+    pub fn set_registry(registry: String) -> Result<(), ParseError> {
+        if Url::parse(&registry).is_err() {
+            return Err(ParseError::InvalidArgument(registry));
+        }
+
+        let _ = REGISTRY_FLAG.set(registry);
+        Ok(())
+    }
+
+    /// The registry from `--registry`, `PIE_REGISTRY` or the `.pierc`, or the `registry` of
+    /// the `.npmrc`, or npm's.
+    fn registry_url() -> String {
+        let registry = REGISTRY_FLAG
+            .get()
+            .cloned()
+            .or_else(|| CONFIG.registry.clone())
+            .or_else(|| NPMRC.get("registry").cloned())
+            .unwrap_or(String::from(REGISTRY_URL));
+
+        registry.trim_end_matches('/').to_string()
+    }
+
+    /// The `.npmrc` registry of the scope of a scoped package, or else the configured one.
+    fn registry_for(route: &str) -> String {
+        route
+            .split_once('/')
+            .filter(|(scope, _)| scope.starts_with('@'))
+            .and_then(|(scope, _)| NPMRC.scope_registry(scope))
+            .map(|registry| registry.trim_end_matches('/').to_string())
+            .unwrap_or(REGISTRY.to_string())
+    }
+
+    /// The token never goes to a tarball host or to another registry.
+    fn authorize(request: RequestBuilder, url: &str) -> RequestBuilder {
+        if Self::same_host(url, &REGISTRY) {
+            return match AUTH_TOKEN.as_ref() {
+                Some(token) => request.bearer_auth(token),
+                None => request,
+            };
+        }
+
+        let token = NPMRC
+            .scope_registries()
+            .find(|registry| Self::same_host(url, registry))
+            .and_then(|registry| NPMRC.auth_token(registry));
+        match token {
+            Some(token) => request.bearer_auth(token),
+            None => request,
+        }
+    }
+
+    fn same_host(url: &str, other: &str) -> bool {
+        match (Url::parse(url), Url::parse(other)) {
+            (Ok(url), Ok(other)) => {
+                url.host_str() == other.host_str()
+                    && url.port_or_known_default() == other.port_or_known_default()
+            }
+            _ => false,
+        }
+    }
+
+    /// Retries connection errors, timeouts and 5xx responses with an exponential backoff. 401
+    /// and 403 are authentication failures, other 4xx responses are returned as is.
+    async fn send(url: String, request: RequestBuilder) -> Result<Response, CommandError> {
+        let request = Self::authorize(request, &url);
+        let mut attempt = 0;
+
+        loop {
+            let result = request
+                .try_clone()
+                .expect("Request should be cloneable")
+                .send()
+                .await;
+
+            let should_retry = match &result {
+                Ok(response) => response.status().is_server_error(),
+                Err(e) => e.is_connect() || e.is_timeout(),
+            };
+
+            if let Ok(response) = &result {
+                if let StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN = response.status() {
+                    return Err(CommandError::Unauthorized(url, response.status().as_u16()));
+                }
+            }
+
+            if !should_retry || attempt >= *HTTP_RETRIES {
+                return result
+                    .and_then(|response| match response.status().is_server_error() {
+                        true => response.error_for_status(),
+                        false => Ok(response),
+                    })
+                    .map_err(|e| Self::http_error(&url, e));
+            }
+
+            attempt += 1;
+            let backoff = BASE_BACKOFF_MS * 2u64.pow(attempt - 1);
+            warn!(
+                "Request failed, retrying in {backoff}ms ({attempt}/{})",
+                *HTTP_RETRIES
+            );
+            tokio::time::sleep(Duration::from_millis(backoff)).await;
+        }
+    }
+
+    /// Revalidated with the ETag of the cached document, a 304 gets the cached body.
+    async fn registry(client: Client, route: String) -> Result<RegistryResponse, CommandError> {
+        let cached = Cache::read_metadata(&route);
+        let etag = cached.as_ref().map(|cached| cached.etag.as_str());
+        let response = Self::registry_query(client, route.to_string(), &[], etag).await?;
+
+        match (response.status, cached) {
+            (StatusCode::NOT_MODIFIED, Some(cached)) => Ok(RegistryResponse {
+                body: cached.body,
+                ..response
+            }),
+            (StatusCode::OK, _) => {
+                if let Some(etag) = &response.etag {
+                    let metadata = CachedMetadata {
+                        etag: etag.to_string(),
+                        body: response.body.to_string(),
+                    };
+                    Cache::write_metadata(&route, &metadata);
+                }
+                Ok(response)
+            }
+            _ => Ok(response),
+        }
+    }
+
+    async fn registry_query(
+        client: Client,
+        route: String,
+        query: &[(&str, String)],
+        etag: Option<&str>,
+    ) -> Result<RegistryResponse, CommandError> {
+        let url = format!("{}/{}", Self::registry_for(&route), route);
+        let mut request = client
+            .get(&url)
+            .query(query)
+            .timeout(*TIMEOUT)
+            .header(ACCEPT, REGISTRY_ACCEPT);
+        if let Some(etag) = etag {
+            request = request.header(IF_NONE_MATCH, etag);
+        }
+
+        let response = Self::send(url.to_string(), request).await?;
+        let status = response.status();
+        let etag = response
+            .headers()
+            .get(ETAG)
+            .and_then(|etag| etag.to_str().ok())
+            .map(String::from);
+        let body = response.text().await.map_err(|e| match e.is_timeout() {
+            true => CommandError::Timeout(url),
+            false => CommandError::FailedResponseText(e),
+        })?;
+
+        Ok(RegistryResponse { status, etag, body })
+    }
+
+    /// Concurrent requesters wait for the first fetch instead of asking the registry again.
+    async fn package_document(
+        client: Client,
+        package_name: &String,
+    ) -> Result<String, CommandError> {
+        let document = utils::lock(&PACKAGE_DOCUMENTS)
+            .entry(package_name.to_string())
+            .or_default()
+            .clone();
+
+        document
+            .get_or_try_init(|| async {
+                Self::registry(client, package_name.to_string())
+                    .await?
+                    .found()
+                    .ok_or(CommandError::PackageNotFound(package_name.to_string()))
+            })
             .await
-            .map_err(CommandError::FailedResponseText)
+            .cloned()
+    }
+
+    /// Starts a download, leaving it to the caller to buffer or stream the body. With an
+    /// offset, only the bytes from there are asked for, which the server may ignore, or
+    /// refuse with a 416 when they are past the end of the file.
+    pub async fn download(
+        client: Client,
+        url: String,
+        offset: u64,
+    ) -> Result<Response, CommandError> {
+        let mut request = client.get(&url).timeout(*TIMEOUT * TARBALL_TIMEOUT_FACTOR);
+        if offset > 0 {
+            request = request.header(RANGE, format!("bytes={offset}-"));
+        }
+
+        let response = Self::send(url.to_string(), request).await?;
+        match response.status() {
+            StatusCode::RANGE_NOT_SATISFIABLE if offset > 0 => Ok(response),
+            _ => response
+                .error_for_status()
+                .map_err(|e| Self::http_error(&url, e)),
+        }
+    }
+
+    /// Reads a download until `on_chunk` returns false. `start` is the offset the response was
+    /// asked from. A dropped connection is resumed from the last byte received.
+    pub async fn read_download(
+        client: Client,
+        url: String,
+        response: Response,
+        start: u64,
+        mut on_chunk: impl FnMut(Bytes, Progress) -> bool,
+    ) -> Result<(), CommandError> {
+        // a resumed response only has the length of the rest
+        let total = response.content_length().map(|length| start + length);
+        let mut response = response;
+        let mut received = start as usize;
+        // a server ignoring the range sends the whole tarball again, its start is dropped
+        let mut to_skip = 0;
+        let mut attempt = 0;
+
+        loop {
+            let e = match response.chunk().await {
+                Ok(Some(chunk)) => {
+                    let skipped = to_skip.min(chunk.len());
+                    to_skip -= skipped;
+                    let chunk = chunk.slice(skipped..);
+                    if chunk.is_empty() {
+                        continue;
+                    }
+
+                    received += chunk.len();
+                    let progress = Progress {
+                        received: received as u64,
+                        total,
+                    };
+                    match on_chunk(chunk, progress) {
+                        true => continue,
+                        false => return Ok(()),
+                    }
+                }
+                Ok(None) => return Ok(()),
+                Err(e) => e,
+            };
+
+            if attempt >= *HTTP_RETRIES {
+                return Err(match e.is_timeout() {
+                    true => CommandError::Timeout(url),
+                    false => CommandError::FailedResponseBytes(e),
+                });
+            }
+
+            attempt += 1;
+            warn!(
+                "Download of '{url}' interrupted after {received} bytes, resuming ({attempt}/{})",
+                *HTTP_RETRIES
+            );
+            let request = client
+                .get(&url)
+                .timeout(*TIMEOUT * TARBALL_TIMEOUT_FACTOR)
+                .header(RANGE, format!("bytes={received}-"));
+            response = Self::send(url.to_string(), request)
+                .await?
+                .error_for_status()
+                .map_err(|e| Self::http_error(&url, e))?;
+            to_skip = match response.status() {
+                StatusCode::PARTIAL_CONTENT => 0,
+                _ => received,
+            };
+        }
     }
 
     pub async fn get_bytes(client: Client, url: String) -> Result<Bytes, CommandError> {
-        client
-            .get(url)
-            .send()
-            .await
-            .map_err(CommandError::HTTPFailed)?
-            .bytes()
-            .await
-            .map_err(CommandError::FailedResponseBytes)
+        let response = Self::download(client.clone(), url.to_string(), 0).await?;
+        let mut bytes = Vec::new();
+        Self::read_download(client, url, response, 0, |chunk, _| {
+            bytes.extend_from_slice(&chunk);
+            true
+        })
+        .await?;
+        Ok(Bytes::from(bytes))
     }
 
     pub async fn version_data(
@@ -36,15 +377,78 @@ impl HttpRequest {
         package_name: &String,
         version: &String,
     ) -> Result<VersionData, CommandError> {
-        let response = Self::registry(client, format!("{package_name}/{version}")).await?;
+        // the registry document of the package, when it was fetched already, has every version
+        let document = utils::lock(&PACKAGE_DOCUMENTS)
+            .get(package_name)
+            .and_then(|document| document.get().cloned());
+        let fetched = document
+            .and_then(|document| serde_json::from_str::<PackageData>(&document).ok())
+            .and_then(|mut package_data| package_data.versions.remove(version));
+        if let Some(version_data) = fetched {
+            return Ok(version_data);
+        }
+
+        let response = Self::registry(client, format!("{package_name}/{version}"))
+            .await?
+            .found()
+            .ok_or(CommandError::PackageNotFound(format!(
+                "{package_name}@{version}"
+            )))?;
         serde_json::from_str::<VersionData>(&response).map_err(CommandError::ParsingFailed)
     }
 
+    /// As far as the registry document fetched by this command tells, an exact version
+    /// installed without it isn't.
+    pub fn is_latest(package_name: &String, version: &String) -> bool {
+        let document = utils::lock(&PACKAGE_DOCUMENTS)
+            .get(package_name)
+            .and_then(|document| document.get().cloned());
+        document
+            .and_then(|document| serde_json::from_str::<PackageData>(&document).ok())
+            .is_some_and(|package_data| package_data.dist_tags.get(LATEST) == Some(version))
+    }
+
     pub async fn package_data(
         client: Client,
         package_name: &String,
     ) -> Result<PackageData, CommandError> {
-        let response = Self::registry(client, format!("{package_name}")).await?;
+        let response = Self::package_document(client, package_name).await?;
         serde_json::from_str::<PackageData>(&response).map_err(CommandError::ParsingFailed)
     }
+
+    pub async fn advisories(
+        client: Client,
+        versions: &BTreeMap<String, Vec<String>>,
+    ) -> Result<HashMap<String, Vec<Advisory>>, CommandError> {
+        let url = format!("{}/{}", *REGISTRY, ADVISORIES_ROUTE);
+        let body = serde_json::to_string(versions).map_err(CommandError::ParsingFailed)?;
+        let request = client
+            .post(&url)
+            .timeout(*TIMEOUT)
+            .header(CONTENT_TYPE, "application/json")
+            .body(body);
+
+        let response = Self::send(url.to_string(), request).await?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Err(CommandError::AuditUnsupported);
+        }
+        let body = response.text().await.map_err(|e| match e.is_timeout() {
+            true => CommandError::Timeout(url),
+            false => CommandError::FailedResponseText(e),
+        })?;
+        serde_json::from_str(&body).map_err(CommandError::ParsingFailed)
+    }
+
+    pub async fn search(
+        client: Client,
+        text: &str,
+        limit: usize,
+    ) -> Result<SearchResults, CommandError> {
+        let query = [("text", text.to_string()), ("size", limit.to_string())];
+        let response = Self::registry_query(client, String::from("-/v1/search"), &query, None)
+            .await?
+            .found()
+            .ok_or(CommandError::SearchUnsupported)?;
+        serde_json::from_str::<SearchResults>(&response).map_err(CommandError::ParsingFailed)
+    }
 }
@@ -1,55 +1,447 @@
 use crate::cache::{Cache, CACHE_DIR};
+use crate::config::CONFIG;
 use crate::errors::CommandError;
-use crate::http::HttpRequest;
-use crate::types::{DependencyMap, PackageLock, VersionData};
-use crate::utils::{TaskAllocator, LATEST};
-use crate::versions::Versions;
+use crate::http::{HttpRequest, Progress};
+use crate::metrics::InstallMetrics;
+use crate::package_json::PackageJson;
+use crate::project_lock::LockedVersions;
+use crate::types::{
+    DependencyMap, Dist, PackageLock, ProjectLock, RequestedDependency, VersionData,
+};
+use crate::utils::{self, TaskAllocator, LATEST};
+use crate::versions::{Preference, VersionRange, VersionSpec, Versions};
 use bytes::Bytes;
-use reqwest::Client;
-use semver::Comparator;
-use std::collections::HashMap;
-use std::sync::mpsc::Sender;
+use lazy_static::lazy_static;
+use log::{debug, info, warn};
+use reqwest::{Client, StatusCode};
+use semver::{Version, VersionReq};
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::path::Path;
+use std::process::Command;
+use std::sync::mpsc::{channel, Receiver, Sender};
 use std::sync::{Arc, Mutex};
+use tokio::sync::{OnceCell, Semaphore};
 
-pub type PackageBytes = (String, Bytes);
+const DEFAULT_MAX_CONCURRENCY: usize = 10;
+
+lazy_static! {
+    static ref DOWNLOAD_PERMITS: Semaphore = Semaphore::new(
+        CONFIG
+            .max_concurrency
+            .filter(|permits| *permits > 0)
+            .unwrap_or(DEFAULT_MAX_CONCURRENCY)
+    );
+    static ref NODE_VERSION: Option<Version> = Command::new("node")
+        .arg("--version")
+        .output()
+        .ok()
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .and_then(|version| Version::parse(version.trim().trim_start_matches('v')).ok());
+}
+
+/// Tarballs up to this size are buffered and checked before extraction, bigger ones are streamed.
+const STREAMING_THRESHOLD: u64 = 1024 * 1024;
+/// In percent of the tarball.
+const PROGRESS_STEP: u64 = 25;
+
+pub enum Tarball {
+    Buffered(Bytes),
+    Streamed(Receiver<Bytes>, Dist),
+}
+
+pub type PackageBytes = (String, Tarball);
 pub type DependencyMapMutex = Arc<Mutex<DependencyMap>>;
+pub type FailuresMutex = Arc<Mutex<Vec<(String, CommandError)>>>;
+pub type DeprecationsMutex = Arc<Mutex<Vec<(String, String)>>>;
+/// The version data fetched for each requested `name@version`, shared by concurrent requesters.
+pub type InFlightMutex = Arc<Mutex<HashMap<String, Arc<OnceCell<VersionData>>>>>;
+
+#[derive(Clone, Copy, Default, PartialEq)]
+pub enum CacheMode {
+    /// Only the version the registry would resolve to is taken from the cache.
+    #[default]
+    Default,
+    /// Any cached version satisfying a range is used, the registry is only asked otherwise.
+    PreferOffline,
+    /// Like `PreferOffline`, without ever asking the registry.
+    Offline,
+    /// The cache is ignored, every package is downloaded again and replaces its cache entry.
+    Refresh,
+}
+
+impl CacheMode {
+    pub fn flag(&self) -> Option<&'static str> {
+        match self {
+            CacheMode::Default => None,
+            CacheMode::PreferOffline => Some("--prefer-offline"),
+            CacheMode::Offline => Some("--offline"),
+            CacheMode::Refresh => Some("--force"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Default, PartialEq)]
+pub enum ScriptPolicy {
+    /// The scripts aren't run, but the packages having one are listed.
+    #[default]
+    Skip,
+    Run,
+    /// The scripts are neither run nor listed.
+    Ignore,
+}
 
 #[derive(Clone)]
 pub struct InstallContext {
     pub client: Client,
     pub sender: Sender<PackageBytes>,
     pub dependency_map_mx: DependencyMapMutex,
+    pub failures: FailuresMutex,
+    pub deprecations: DeprecationsMutex,
+    pub metrics: Arc<InstallMetrics>,
+    pub in_flight: InFlightMutex,
+    pub locked_versions: Option<Arc<LockedVersions>>,
+    pub frozen: bool,
+    pub locked_integrities: Option<Arc<HashMap<String, String>>>,
+    pub overrides: Arc<HashMap<String, String>>,
+    pub cache_mode: CacheMode,
+    pub preference: Preference,
+    pub script_policy: ScriptPolicy,
+    pub dry_run: bool,
+}
+
+impl InstallContext {
+    pub fn new(
+        locked_versions: Option<Arc<LockedVersions>>,
+        cache_mode: CacheMode,
+        dry_run: bool,
+    ) -> (Self, Receiver<PackageBytes>) {
+        let (sender, receiver) = channel::<PackageBytes>();
+        let context = Self {
+            client: HttpRequest::client(),
+            sender,
+            dependency_map_mx: Arc::new(Mutex::new(HashMap::new())),
+            failures: Arc::new(Mutex::new(Vec::new())),
+            deprecations: Arc::new(Mutex::new(Vec::new())),
+            metrics: Arc::new(InstallMetrics::default()),
+            in_flight: Arc::new(Mutex::new(HashMap::new())),
+            locked_versions,
+            frozen: false,
+            locked_integrities: None,
+            overrides: Arc::new(HashMap::new()),
+            cache_mode,
+            preference: Preference::Highest,
+            script_policy: match CONFIG.run_scripts {
+                Some(true) => ScriptPolicy::Run,
+                Some(false) => ScriptPolicy::Ignore,
+                None => ScriptPolicy::Skip,
+            },
+            dry_run,
+        };
+
+        (context, receiver)
+    }
 }
 
 pub struct PackageInfo {
     pub version_data: VersionData,
     pub is_latest: bool,
     pub stringified: String,
+    /// An optional dependency that fails to install is skipped instead of failing its parent.
+    pub optional: bool,
+    /// The tarball, when it had to be downloaded to resolve the package.
+    pub tarball: Option<Bytes>,
 }
 
 pub struct Installer;
 impl Installer {
+    /// Looks a package up in the cache. `full_version` is the version the registry would
+    /// resolve the spec to, when it is known without asking it.
+    pub async fn find_cached(
+        context: &InstallContext,
+        package_name: &String,
+        version_spec: &VersionSpec,
+        full_version: Option<&String>,
+    ) -> Result<(bool, Option<String>), CommandError> {
+        if let VersionSpec::Tarball(_) | VersionSpec::Local(_) = version_spec {
+            return Ok((false, None));
+        }
+
+        let (is_cached, cached_version) =
+            Self::find_in_cache(context, package_name, version_spec, full_version).await?;
+
+        // a package whose cached files, or the ones of its dependencies, changed since they
+        // were installed is installed again, which downloads the altered ones
+        if let (true, Some(version)) = (is_cached, &cached_version) {
+            let altered =
+                Self::altered_packages(context, &Versions::stringify(package_name, version));
+            for (package, reason) in altered.iter() {
+                warn!("Warning: {reason}, downloading '{package}' again");
+                Cache::invalidate(package);
+            }
+
+            if !altered.is_empty() && context.cache_mode == CacheMode::Offline {
+                let altered = altered
+                    .into_iter()
+                    .map(|(package, _)| package)
+                    .collect::<Vec<_>>();
+                return Err(CommandError::NotCached(altered.join(", ")));
+            }
+            if !altered.is_empty() {
+                return Ok((false, None));
+            }
+        }
+
+        // a package missing some of its dependencies is installed again, which downloads them
+        if let (true, Some(version)) = (is_cached, &cached_version) {
+            let missing = Cache::missing_dependencies(&Versions::stringify(package_name, version));
+            if !missing.is_empty() && context.cache_mode == CacheMode::Offline {
+                return Err(CommandError::NotCached(missing.join(", ")));
+            }
+
+            if !missing.is_empty() {
+                debug!(
+                    "Cached package '{}@{}' is missing {}, installing it again",
+                    package_name,
+                    version,
+                    missing.join(", ")
+                );
+                return Ok((false, None));
+            }
+        }
+
+        // the dependencies of a package resolved under other overrides may not be the ones
+        // they would resolve to now, it is installed again
+        if let (true, Some(version)) = (is_cached, &cached_version) {
+            let stringified = Versions::stringify(package_name, version);
+            let resolved_otherwise = Cache::read_lock(&stringified).is_ok_and(|lock| {
+                lock.overrides != Self::applied_overrides(&context.overrides, &lock.dependencies)
+            });
+            if resolved_otherwise {
+                if context.cache_mode == CacheMode::Offline {
+                    return Err(CommandError::NotCached(stringified));
+                }
+
+                debug!("Cached package '{stringified}' was resolved under other overrides, installing it again");
+                return Ok((false, None));
+            }
+        }
+
+        Ok((is_cached, cached_version))
+    }
+
+    pub fn applied_overrides(
+        overrides: &HashMap<String, String>,
+        dependencies: &[String],
+    ) -> BTreeMap<String, String> {
+        dependencies
+            .iter()
+            .filter_map(|dependency| {
+                let (name, _) = Versions::parse_raw_package_details(dependency.to_string());
+                let version = overrides.get(&name)?;
+                Some((name, version.to_string()))
+            })
+            .collect()
+    }
+
+    /// The cached packages whose files changed since they were extracted, or, for a frozen
+    /// install, that came from another tarball than the locked one.
+    fn altered_packages(context: &InstallContext, package: &String) -> Vec<(String, &'static str)> {
+        let dependencies = Cache::read_lock(package)
+            .map(|lock| lock.dependencies)
+            .unwrap_or_default();
+
+        [package.to_string()]
+            .into_iter()
+            .chain(dependencies)
+            .filter_map(|package| {
+                let is_locked_tarball = context
+                    .locked_integrities
+                    .as_ref()
+                    .and_then(|integrities| integrities.get(&package))
+                    .is_none_or(|integrity| {
+                        Cache::read_lock(&package)
+                            .ok()
+                            .and_then(|lock| lock.integrity)
+                            .is_none_or(|cached| &cached == integrity)
+                    });
+
+                if !is_locked_tarball {
+                    Some((package, "its tarball isn't the one locked in pie-lock.json"))
+                } else if !Cache::is_unaltered(&package) {
+                    Some((package, "its cached files changed since it was installed"))
+                } else {
+                    None
+                }
+            })
+            .collect()
+    }
+
+    async fn find_in_cache(
+        context: &InstallContext,
+        package_name: &String,
+        version_spec: &VersionSpec,
+        full_version: Option<&String>,
+    ) -> Result<(bool, Option<String>), CommandError> {
+        match context.cache_mode {
+            CacheMode::Default => {
+                return Cache::exists(package_name, full_version, version_spec.range()).await
+            }
+            CacheMode::Refresh => return Ok((false, None)),
+            CacheMode::PreferOffline | CacheMode::Offline => {}
+        }
+
+        match version_spec {
+            VersionSpec::Range(range) => Cache::exists(package_name, None, Some(range)).await,
+            VersionSpec::Tag(tag) if tag == LATEST => {
+                let any_version = VersionRange(vec![VersionReq::STAR]);
+                Cache::exists(package_name, None, Some(&any_version)).await
+            }
+            // other dist-tags can only be resolved by the registry
+            VersionSpec::Tag(_) | VersionSpec::Tarball(_) | VersionSpec::Local(_) => {
+                Cache::exists(package_name, full_version, None).await
+            }
+        }
+    }
+
+    /// Only an exact version is resolved without the registry, unless the cache mode prefers
+    /// the cache, so that an uncached higher match isn't missed.
+    pub async fn resolve_full_version(
+        context: &InstallContext,
+        package_name: &String,
+        version_spec: &VersionSpec,
+    ) -> Result<Option<String>, CommandError> {
+        let full_version = Versions::resolve_full_version(version_spec);
+        let asks_registry =
+            context.preference == Preference::Lowest || context.cache_mode == CacheMode::Default;
+        let (None, true, Some(range)) = (&full_version, asks_registry, version_spec.range()) else {
+            return Ok(full_version);
+        };
+
+        let package_data = HttpRequest::package_data(context.client.clone(), package_name).await?;
+        Versions::resolve_partial_version(Some(range), &package_data, context.preference).map(Some)
+    }
+
     pub async fn get_version_data(
-        client: Client,
+        context: &InstallContext,
         package_name: &String,
         full_version: Option<&String>,
-        version: Option<&Comparator>,
+        version: Option<&VersionRange>,
     ) -> Result<VersionData, CommandError> {
+        // dist-tags, `latest` included, are looked up in the package data rather than trusting
+        // the registry to resolve them
         if let Some(v) = full_version {
-            return HttpRequest::version_data(client.clone(), package_name, v).await;
+            if !Versions::is_dist_tag(v) {
+                return HttpRequest::version_data(context.client.clone(), package_name, v).await;
+            }
         }
 
-        let mut package_data = HttpRequest::package_data(client.clone(), package_name).await?;
-        let package_version = Versions::resolve_partial_version(version, &package_data.versions)?;
+        let mut package_data =
+            HttpRequest::package_data(context.client.clone(), package_name).await?;
+        let package_version = match full_version {
+            Some(tag) => package_data
+                .dist_tags
+                .get(tag)
+                .cloned()
+                .ok_or(CommandError::UnknownDistTag(tag.to_string()))?,
+            None => Versions::resolve_partial_version(version, &package_data, context.preference)?,
+        };
 
-        Ok(package_data
+        package_data
             .versions
             .remove(&package_version)
-            .expect("Failed to find resolved package version in package data"))
+            .ok_or(CommandError::InvalidVersion(package_version))
+    }
+
+    /// Fetched once per `name@version`, concurrent requesters wait for the first fetch.
+    async fn fetch_version_data(
+        context: &InstallContext,
+        package_name: &String,
+        full_version: Option<&String>,
+        version: Option<&VersionRange>,
+    ) -> Result<VersionData, CommandError> {
+        let requested = match (full_version, version) {
+            (Some(full_version), _) => full_version.to_string(),
+            (None, Some(range)) => range.to_string(),
+            (None, None) => String::from(LATEST),
+        };
+        let cell = Arc::clone(
+            utils::lock(&context.in_flight)
+                .entry(Versions::stringify(package_name, &requested))
+                .or_default(),
+        );
+
+        // a failed fetch leaves the cell empty, so the next requester tries again
+        cell.get_or_try_init(|| {
+            Self::get_version_data(context, package_name, full_version, version)
+        })
+        .await
+        .cloned()
+    }
+
+    pub async fn fetch_tarball(
+        context: &InstallContext,
+        url: &String,
+    ) -> Result<(VersionData, Bytes), CommandError> {
+        let bytes = HttpRequest::get_bytes(context.client.clone(), url.to_string()).await?;
+        context.metrics.add_bytes(bytes.len());
+        Ok((Self::read_tarball(&bytes, url)?, bytes))
+    }
+
+    pub fn read_tarball(bytes: &Bytes, source: &String) -> Result<VersionData, CommandError> {
+        let raw = utils::read_tarball_package_json(bytes, source)?;
+        let mut package_json =
+            serde_json::from_str::<Value>(&raw).map_err(CommandError::ParsingFailed)?;
+
+        // without a registry, there is no checksum to verify the tarball against
+        if let Some(package_json) = package_json.as_object_mut() {
+            package_json.insert(String::from("dist"), json!({ "tarball": source }));
+        }
+
+        serde_json::from_value::<VersionData>(package_json).map_err(CommandError::ParsingFailed)
+    }
+
+    fn check_engines(package_info: &PackageInfo) {
+        let Some(node_range) = package_info
+            .version_data
+            .engines
+            .as_ref()
+            .and_then(|engines| engines.get("node"))
+        else {
+            return;
+        };
+
+        let (Some(node_version), Ok(range)) = (
+            NODE_VERSION.as_ref(),
+            Versions::parse_semantic_version(node_range),
+        ) else {
+            return;
+        };
+
+        if !range.matches(node_version) {
+            warn!(
+                "Warning: package '{}' requires node {}, but node {} is installed",
+                package_info.stringified, node_range, node_version
+            );
+        }
+    }
+
+    fn check_deprecated(context: &InstallContext, package_info: &PackageInfo) {
+        let Some(message) = &package_info.version_data.deprecated else {
+            return;
+        };
+
+        warn!(
+            "Warning: package '{}' is deprecated: {}",
+            package_info.stringified, message
+        );
+        utils::lock(&context.deprecations)
+            .push((package_info.stringified.to_string(), message.to_string()));
     }
 
     fn already_resolved(context: &InstallContext, package_info: &PackageInfo) -> bool {
-        let mut dependency_map = context.dependency_map_mx.lock().unwrap();
+        let mut dependency_map = utils::lock(&context.dependency_map_mx);
         let stringified = Versions::stringify(
             &package_info.version_data.name,
             &package_info.version_data.version,
@@ -60,125 +452,611 @@ impl Installer {
         match installed_versions {
             Some(_) => true,
             None => {
-                dependency_map.insert(stringified, PackageLock::new(package_info.is_latest));
+                let mut lock = PackageLock::new(package_info.is_latest);
+                lock.bin = package_info.version_data.bins();
+                lock.integrity = package_info.version_data.dist.integrity.clone();
+                lock.peer_dependencies = package_info
+                    .version_data
+                    .peer_dependencies
+                    .clone()
+                    .unwrap_or_default();
+                dependency_map.insert(stringified, lock);
                 false
             }
         }
     }
 
+    /// Records a package as a dependency of every package of the resolution path leading to it.
     fn append_version(
-        parents_mux: Arc<Mutex<Vec<String>>>,
+        parents: &[String],
         new_version_name: String,
         dependency_map_mx: DependencyMapMutex,
     ) -> Result<(), CommandError> {
-        let mut dependency_map = dependency_map_mx.lock().unwrap();
-        let parents = parents_mux.lock().unwrap();
+        let mut dependency_map = utils::lock(&dependency_map_mx);
 
-        for parent in parents.iter() {
+        for parent in parents.iter().filter(|parent| **parent != new_version_name) {
             let parent_version = dependency_map
                 .entry(parent.to_string())
                 .or_insert(PackageLock::new(parent.ends_with(LATEST)));
-            parent_version
-                .dependencies
-                .push(new_version_name.to_string());
+
+            if !parent_version.dependencies.contains(&new_version_name) {
+                parent_version
+                    .dependencies
+                    .push(new_version_name.to_string());
+            }
         }
 
         Ok(())
     }
 
+    fn record_requested(
+        parents: &[String],
+        name: &String,
+        range: &str,
+        stringified: &String,
+        dependency_map_mx: &DependencyMapMutex,
+    ) {
+        let Some(parent) = parents.last() else {
+            return;
+        };
+
+        let (_, version) = Versions::parse_raw_package_details(stringified.to_string());
+        let mut dependency_map = utils::lock(dependency_map_mx);
+        if let Some(lock) = dependency_map.get_mut(parent) {
+            let requested = RequestedDependency {
+                range: range.to_string(),
+                version,
+            };
+            lock.requested.insert(name.to_string(), requested);
+        }
+    }
+
+    pub fn unmet_peer_dependencies(
+        dependency_map: &DependencyMap,
+        installed: &[String],
+    ) -> Vec<String> {
+        let installed = installed
+            .iter()
+            .chain(dependency_map.keys())
+            .map(|package| Versions::parse_raw_package_details(package.to_string()))
+            .collect::<HashMap<_, _>>();
+
+        let mut unmet = Vec::new();
+        for (package, lock) in dependency_map.iter() {
+            for (peer_name, peer_range) in lock.peer_dependencies.iter() {
+                let is_satisfied = match (
+                    installed.get(peer_name),
+                    Versions::parse_semantic_version(peer_range),
+                ) {
+                    (Some(version), Ok(range)) => {
+                        Version::parse(version).is_ok_and(|version| range.matches(&version))
+                    }
+                    // a range pie can't parse is given the benefit of the doubt
+                    (Some(_), Err(_)) => true,
+                    (None, _) => false,
+                };
+
+                if is_satisfied {
+                    continue;
+                }
+
+                let found = match installed.get(peer_name) {
+                    Some(version) => format!("found {version}"),
+                    None => String::from("missing"),
+                };
+                unmet.push(format!(
+                    "{package} requires {peer_name}@{peer_range}, {found}"
+                ));
+            }
+        }
+
+        unmet.sort();
+        unmet
+    }
+
+    pub fn postinstall_script(package: &String) -> Option<String> {
+        let path = format!("{}/{}/package/package.json", *CACHE_DIR, package);
+        let mut package_json = PackageJson::read_from(Path::new(&path)).ok()?;
+        package_json.scripts.remove("postinstall")
+    }
+
+    /// Runs a script from the cache entry of its package, with the executables of the
+    /// project on the PATH.
+    pub fn run_script(package: &String, script: &str) -> Result<(), CommandError> {
+        let fail = |reason: String| CommandError::ScriptFailed(package.to_string(), reason);
+
+        let bin_dir = env::current_dir()
+            .map_err(|e| fail(e.to_string()))?
+            .join("node_modules/.bin");
+        let paths = env::var_os("PATH").unwrap_or_default();
+        let path = env::join_paths([bin_dir].into_iter().chain(env::split_paths(&paths)))
+            .map_err(|e| fail(e.to_string()))?;
+
+        #[cfg(windows)]
+        let mut command = Command::new("cmd");
+        #[cfg(windows)]
+        command.arg("/C");
+        #[cfg(not(windows))]
+        let mut command = Command::new("sh");
+        #[cfg(not(windows))]
+        command.arg("-c");
+
+        let status = command
+            .arg(script)
+            .current_dir(format!("{}/{}/package", *CACHE_DIR, package))
+            .env("PATH", path)
+            .status()
+            .map_err(|e| fail(e.to_string()))?;
+
+        match status.success() {
+            true => Ok(()),
+            false => Err(fail(status.to_string())),
+        }
+    }
+
+    fn remove_version(version_name: &String, dependency_map_mx: DependencyMapMutex) {
+        let mut dependency_map = utils::lock(&dependency_map_mx);
+
+        dependency_map.remove(version_name);
+        for lock in dependency_map.values_mut() {
+            lock.dependencies
+                .retain(|dependency| dependency != version_name);
+            lock.requested.retain(|name, requested| {
+                &Versions::stringify(name, &requested.version) != version_name
+            });
+        }
+    }
+
+    /// `parents` is the resolution path leading to the package, from the top-level one.
     pub fn install_package(
         context: InstallContext,
         package_info: PackageInfo,
-        parents_mux: Arc<Mutex<Vec<String>>>,
+        parents: Vec<String>,
     ) -> Result<(), CommandError> {
+        // a package that is its own ancestor closes a cycle, it is already being installed
+        if parents.contains(&package_info.stringified) {
+            debug!(
+                "Package '{}' is part of a dependency cycle, considering it satisfied",
+                package_info.stringified
+            );
+            return Self::append_version(
+                &parents,
+                package_info.stringified,
+                Arc::clone(&context.dependency_map_mx),
+            );
+        }
+
         if Self::already_resolved(&context, &package_info) {
-            println!("Package '{}' already resolved", package_info.stringified);
-            return Ok(());
+            debug!("Package '{}' already resolved", package_info.stringified);
+            return Self::append_version(
+                &parents,
+                package_info.stringified,
+                Arc::clone(&context.dependency_map_mx),
+            );
         }
 
+        Self::check_engines(&package_info);
+        Self::check_deprecated(&context, &package_info);
         Self::append_version(
-            Arc::clone(&parents_mux),
+            &parents,
             package_info.stringified.to_string(),
             Arc::clone(&context.dependency_map_mx),
-        )
-        .unwrap();
-        {
-            let mut parents = parents_mux.lock().unwrap();
-            parents.push(package_info.stringified.to_string());
-        }
+        )?;
+
+        let mut parents = parents;
+        parents.push(package_info.stringified.to_string());
 
-        println!(
+        debug!(
             "Launching task to download package '{}'",
             package_info.stringified
         );
+        let stringified = package_info.stringified.to_string();
+        let optional = package_info.optional;
         TaskAllocator::add_task(async move {
-            println!("Downloading package '{}'", package_info.stringified);
-            let version_data = package_info.version_data;
-            let package_bytes =
-                HttpRequest::get_bytes(context.client.clone(), version_data.dist.tarball)
+            let result = match context.dry_run {
+                true => {
+                    Self::install_package_dependencies(
+                        context.clone(),
+                        &package_info.stringified,
+                        package_info.version_data,
+                        parents,
+                    )
                     .await
-                    .unwrap();
-            println!("Downloaded package '{}'", package_info.stringified);
+                }
+                false => Self::download_package(context.clone(), package_info, parents).await,
+            };
 
-            println!(
-                "Sending package '{}' to extraction task",
-                package_info.stringified
-            );
-            let package_destination = format!("{}/{}", *CACHE_DIR, package_info.stringified);
+            if let Err(e) = result {
+                Self::fail(&context, stringified, optional, e);
+            }
+        });
+
+        Ok(())
+    }
+
+    fn fail(context: &InstallContext, stringified: String, optional: bool, e: CommandError) {
+        if !optional {
+            utils::lock(&context.failures).push((stringified, e));
+            return;
+        }
+
+        warn!("Warning: skipping optional dependency '{stringified}' ({e})");
+        Self::remove_version(&stringified, Arc::clone(&context.dependency_map_mx));
+    }
+
+    /// Downloads a tarball and hands it over to the extraction task, buffered when it is small
+    /// enough to be checked before extraction and streamed otherwise.
+    async fn download_tarball(
+        context: &InstallContext,
+        stringified: &String,
+        dist: &Dist,
+        package_destination: String,
+    ) -> Result<(), CommandError> {
+        let permit = DOWNLOAD_PERMITS
+            .acquire()
+            .await
+            .expect("Download semaphore should never be closed");
+        debug!("Downloading package '{}'", stringified);
+        // what an interrupted install downloaded of the tarball is kept in the temporary area
+        // of the cache, and only the rest of it is asked for
+        let mut partial = utils::PartialDownload::claim(&package_destination);
+        let mut downloaded = partial.downloaded();
+        let mut response =
+            HttpRequest::download(context.client.clone(), dist.tarball.clone(), downloaded).await?;
+        // the partial download isn't part of the tarball the server has, so it is started over
+        if response.status() == StatusCode::RANGE_NOT_SATISFIABLE {
+            debug!("Restarting the download of '{}'", stringified);
+            partial.remove();
+            partial = utils::PartialDownload::claim(&package_destination);
+            downloaded = 0;
+            response =
+                HttpRequest::download(context.client.clone(), dist.tarball.clone(), 0).await?;
+        }
+        let resumed = downloaded > 0 && response.status() == StatusCode::PARTIAL_CONTENT;
+
+        let is_small = !resumed
+            && response
+                .content_length()
+                .is_some_and(|length| length <= STREAMING_THRESHOLD);
+
+        if is_small {
+            partial.remove();
+            let mut package_bytes = Vec::new();
+            HttpRequest::read_download(
+                context.client.clone(),
+                dist.tarball.clone(),
+                response,
+                0,
+                |chunk, _| {
+                    package_bytes.extend_from_slice(&chunk);
+                    true
+                },
+            )
+            .await?;
+            let package_bytes = Bytes::from(package_bytes);
+            drop(permit);
+            context.metrics.add_bytes(package_bytes.len());
+            debug!("Downloaded package '{}'", stringified);
+
+            // a corrupted or tampered tarball never reaches the cache
+            utils::verify_integrity(&package_bytes, dist, stringified)?;
+
+            debug!("Sending package '{}' to extraction task", stringified);
             context
                 .sender
-                .send((package_destination, package_bytes))
-                .unwrap();
+                .send((package_destination, Tarball::Buffered(package_bytes)))
+                .map_err(|_| CommandError::ExtractionStopped(stringified.to_string()))?;
+        } else {
+            debug!("Streaming package '{}' to extraction task", stringified);
+            let (chunk_sender, chunk_receiver) = channel::<Bytes>();
+            context
+                .sender
+                .send((
+                    package_destination,
+                    Tarball::Streamed(chunk_receiver, dist.clone()),
+                ))
+                .map_err(|_| CommandError::ExtractionStopped(stringified.to_string()))?;
 
-            let dependencies = version_data.dependencies.unwrap_or(HashMap::new());
+            let start = match resumed {
+                true => {
+                    debug!(
+                        "Resuming download of '{}' from {} bytes",
+                        stringified, downloaded
+                    );
+                    let bytes = partial.read()?;
+                    chunk_sender
+                        .send(bytes)
+                        .map_err(|_| CommandError::ExtractionStopped(stringified.to_string()))?;
+                    downloaded
+                }
+                false => 0,
+            };
+            partial.start(resumed);
 
-            println!("Installing dependencies for '{}'", package_info.stringified);
-            Self::install_dependencies(parents_mux, context, dependencies).await;
-        });
+            // a resumed download only receives the rest of the tarball, after the bytes sent
+            // above, while a server ignoring the range sends it all again, over the partial one
+            let mut reported = 0;
+            HttpRequest::read_download(
+                context.client.clone(),
+                dist.tarball.clone(),
+                response,
+                start,
+                |chunk, progress| {
+                    partial.append(&chunk);
+                    context.metrics.add_bytes(chunk.len());
+                    Self::report_progress(stringified, progress, &mut reported);
+                    // the extraction task drops the chunks receiver when it fails, and reports why
+                    chunk_sender.send(chunk).is_ok()
+                },
+            )
+            .await?;
+            drop(permit);
+            partial.remove();
+            debug!("Downloaded package '{}'", stringified);
+        }
 
         Ok(())
     }
 
+    /// Without a `Content-Length`, the size received is reported instead, once per megabyte.
+    fn report_progress(stringified: &String, progress: Progress, reported: &mut u64) {
+        let (step, report) = match progress.percent() {
+            Some(percent) => (percent - percent % PROGRESS_STEP, format!("{percent}%")),
+            None => (
+                progress.received - progress.received % STREAMING_THRESHOLD,
+                utils::format_size(progress.received),
+            ),
+        };
+        if step <= *reported {
+            return;
+        }
+
+        *reported = step;
+        let total = progress.total.map(utils::format_size);
+        match total {
+            Some(total) => info!("Downloading '{stringified}' ... {report} of {total}"),
+            None => info!("Downloading '{stringified}' ... {report}"),
+        }
+    }
+
+    async fn download_package(
+        context: InstallContext,
+        package_info: PackageInfo,
+        parents: Vec<String>,
+    ) -> Result<(), CommandError> {
+        let version_data = package_info.version_data;
+        let package_destination = format!("{}/{}", *CACHE_DIR, package_info.stringified);
+
+        match package_info.tarball {
+            Some(bytes) => context
+                .sender
+                .send((package_destination, Tarball::Buffered(bytes)))
+                .map_err(|_| {
+                    CommandError::ExtractionStopped(package_info.stringified.to_string())
+                })?,
+            None if version_data.dist.tarball.is_empty() => {
+                return Err(CommandError::MissingTarball(
+                    version_data.name,
+                    version_data.version,
+                ));
+            }
+            None => {
+                Self::download_tarball(
+                    &context,
+                    &package_info.stringified,
+                    &version_data.dist,
+                    package_destination,
+                )
+                .await?
+            }
+        }
+        context.metrics.add_downloaded();
+
+        Self::install_package_dependencies(
+            context,
+            &package_info.stringified,
+            version_data,
+            parents,
+        )
+        .await
+    }
+
+    async fn install_package_dependencies(
+        context: InstallContext,
+        stringified: &String,
+        version_data: VersionData,
+        parents: Vec<String>,
+    ) -> Result<(), CommandError> {
+        // bundled dependencies come with the tarball, they are never fetched
+        let bundled = version_data.bundled();
+        let mut optional_dependencies = version_data.optional_dependencies.unwrap_or_default();
+        optional_dependencies.retain(|name, _| !bundled.contains(name));
+        // an optional dependency also listed in `dependencies` is still optional
+        let dependencies = version_data
+            .dependencies
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|(name, _)| {
+                !optional_dependencies.contains_key(name) && !bundled.contains(name)
+            })
+            .collect();
+
+        debug!("Installing dependencies for '{}'", stringified);
+        Self::install_dependencies(parents.clone(), context.clone(), dependencies, false).await?;
+        Self::install_dependencies(parents, context, optional_dependencies, true).await
+    }
+
     async fn install_dependencies(
-        parents_mux: Arc<Mutex<Vec<String>>>,
+        parents: Vec<String>,
         context: InstallContext,
         dependencies: HashMap<String, String>,
-    ) {
+        optional: bool,
+    ) -> Result<(), CommandError> {
         for (name, version) in dependencies {
-            let c = Versions::parse_semantic_version(&version).unwrap();
-            let comparator = Some(&c);
+            let result = Self::install_dependency(&parents, &context, &name, &version, optional)
+                .await
+                .map_err(|e| {
+                    CommandError::InstallFailed(Versions::stringify(&name, &version), e.to_string())
+                });
 
-            let full_version = Versions::resolve_full_version(comparator);
-            let full_version = full_version.as_ref();
+            match result {
+                Err(e) if optional => warn!("Warning: skipping optional dependency ({e})"),
+                result => result?,
+            }
+        }
 
-            let (is_cached, cached_version) = Cache::exists(&name, full_version, comparator)
-                .await
-                .unwrap();
+        Ok(())
+    }
 
-            if is_cached {
-                let version = cached_version.expect("Failed to get cached version");
-                let stringified = Versions::stringify(&name, &version);
+    async fn install_dependency(
+        parents: &[String],
+        context: &InstallContext,
+        name: &String,
+        version: &str,
+        optional: bool,
+    ) -> Result<(), CommandError> {
+        // an override replaces whatever range the dependent asked for
+        let version = match context.overrides.get(name) {
+            Some(overridden) => {
+                debug!("Overriding '{name}@{version}' with '{overridden}'");
+                overridden
+            }
+            None => version,
+        };
+        let mut version_spec =
+            Versions::parse_version_spec(version).map_err(CommandError::InvalidDependencyRange)?;
 
-                let dependency_map = context.dependency_map_mx.lock().unwrap();
-                if dependency_map.get(stringified.as_str()).is_none() {
-                    Cache::load_cached_version(stringified);
-                    continue;
+        let lockable =
+            context.frozen || matches!(version_spec, VersionSpec::Range(_) | VersionSpec::Tag(_));
+        if let (Some(locked_versions), true) = (&context.locked_versions, lockable) {
+            match ProjectLock::locked_version(locked_versions, name, version_spec.range()) {
+                Some(locked_version) => version_spec = Versions::exact_spec(&locked_version),
+                None if context.frozen => {
+                    return Err(CommandError::LockfileOutOfDate(
+                        name.to_string(),
+                        version.to_string(),
+                    ))
                 }
+                // a range the lockfile no longer satisfies is resolved again
+                None => {}
+            }
+        }
+        if let VersionSpec::Tarball(url) = &version_spec {
+            if context.cache_mode == CacheMode::Offline {
+                return Err(CommandError::NotCached(Versions::stringify(name, url)));
             }
 
-            let version_data =
-                Self::get_version_data(context.client.clone(), &name, full_version, comparator)
-                    .await
-                    .unwrap();
-            let stringified = Versions::stringify(&name, &version_data.version);
-
+            let (version_data, bytes) = Self::fetch_tarball(context, url).await?;
             let package_info = PackageInfo {
+                stringified: Versions::stringify(&version_data.name, &version_data.version),
                 version_data,
-                is_latest: Versions::is_latest(Some(&stringified)),
-                stringified,
+                is_latest: false,
+                optional,
+                tarball: Some(bytes),
             };
+            Self::record_requested(
+                parents,
+                name,
+                version,
+                &package_info.stringified,
+                &context.dependency_map_mx,
+            );
+            return Self::install_package(context.clone(), package_info, parents.to_vec());
+        }
 
-            Self::install_package(context.clone(), package_info, Arc::clone(&parents_mux)).unwrap();
+        // a relative path means nothing in a package coming from the registry
+        if let VersionSpec::Local(_) = &version_spec {
+            return Err(CommandError::LocalDependency(Versions::stringify(
+                name,
+                &version.to_string(),
+            )));
         }
+
+        let range_ref = version_spec.range();
+
+        let full_version = Self::resolve_full_version(context, name, &version_spec).await?;
+        let full_version = full_version.as_ref();
+
+        let (is_cached, cached_version) =
+            Self::find_cached(context, name, &version_spec, full_version).await?;
+
+        if is_cached {
+            let cached_version = cached_version.expect("Failed to get cached version");
+            let stringified = Versions::stringify(name, &cached_version);
+
+            let is_resolved =
+                utils::lock(&context.dependency_map_mx).contains_key(stringified.as_str());
+
+            Self::record_requested(
+                parents,
+                name,
+                version,
+                &stringified,
+                &context.dependency_map_mx,
+            );
+
+            if !is_resolved {
+                // the cached package and its own dependencies belong to every parent's lock
+                let cached_dependencies = Cache::read_lock(&stringified)
+                    .map(|lock| lock.dependencies)
+                    .unwrap_or_default();
+                context.metrics.add_cached(
+                    cached_dependencies
+                        .iter()
+                        .cloned()
+                        .chain([stringified.clone()]),
+                );
+                for dependency in cached_dependencies.into_iter().chain([stringified.clone()]) {
+                    Self::append_version(
+                        parents,
+                        dependency,
+                        Arc::clone(&context.dependency_map_mx),
+                    )?;
+                }
+
+                if !context.dry_run {
+                    Cache::load_cached_version(stringified)?;
+                }
+                return Ok(());
+            }
+        }
+
+        if context.cache_mode == CacheMode::Offline {
+            return Err(CommandError::NotCached(Versions::stringify(
+                name,
+                &version.to_string(),
+            )));
+        }
+
+        let version_data = Self::fetch_version_data(context, name, full_version, range_ref).await?;
+        let stringified = Versions::stringify(name, &version_data.version);
+
+        // the optional packages built for each platform are only needed on theirs
+        if optional && !version_data.supports_platform() {
+            debug!(
+                "Skipping optional dependency '{}', it doesn't support {} {}",
+                stringified,
+                utils::npm_os(),
+                utils::npm_cpu()
+            );
+            return Ok(());
+        }
+
+        let package_info = PackageInfo {
+            is_latest: HttpRequest::is_latest(name, &version_data.version),
+            version_data,
+            stringified,
+            optional,
+            tarball: None,
+        };
+
+        Self::record_requested(
+            parents,
+            name,
+            version,
+            &package_info.stringified,
+            &context.dependency_map_mx,
+        );
+        Self::install_package(context.clone(), package_info, parents.to_vec())
     }
 }
@@ -1,20 +1,35 @@
 mod cache;
 mod command_handler;
+mod config;
 mod errors;
+mod graph;
 mod handlers;
 mod http;
 mod installer;
+mod metrics;
+mod npmrc;
+mod package_json;
+mod project_lock;
+mod report;
 mod types;
 mod utils;
 mod versions;
 
+use errors::PieError;
+use log::error;
 use std::env;
+use std::process;
 
 #[tokio::main]
 async fn main() {
-    let parse_result = command_handler::handle_args(env::args()).await;
+    let result = command_handler::handle_args(env::args()).await;
 
-    if let Err(err) = parse_result {
-        println!("Failed to parse command: {err}");
+    if let Err(err) = result {
+        match &err {
+            // the logger isn't set up yet when the global flags can't be parsed
+            PieError::Parse(e) => println!("Failed to parse command: {e}"),
+            PieError::Command(e) => error!("Command error : {e}"),
+        }
+        process::exit(err.exit_code());
     }
 }
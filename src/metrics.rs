@@ -0,0 +1,66 @@
+use crate::utils;
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// What an install did, shared by all of its tasks and summed up once they are done.
+pub struct InstallMetrics {
+    started: Instant,
+    resolved: Mutex<Option<Duration>>,
+    /// Every package linked from the cache, by `name@version`, as several dependents can
+    /// link the same one.
+    cached: Mutex<HashSet<String>>,
+    downloaded: AtomicUsize,
+    downloaded_bytes: AtomicU64,
+}
+
+impl Default for InstallMetrics {
+    fn default() -> Self {
+        Self {
+            started: Instant::now(),
+            resolved: Mutex::new(None),
+            cached: Mutex::new(HashSet::new()),
+            downloaded: AtomicUsize::new(0),
+            downloaded_bytes: AtomicU64::new(0),
+        }
+    }
+}
+
+impl InstallMetrics {
+    /// Marks the end of the resolution of the requested packages.
+    pub fn resolution_done(&self) {
+        *utils::lock(&self.resolved) = Some(self.started.elapsed());
+    }
+
+    pub fn add_cached(&self, packages: impl IntoIterator<Item = String>) {
+        utils::lock(&self.cached).extend(packages);
+    }
+
+    pub fn add_downloaded(&self) {
+        self.downloaded.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn add_bytes(&self, bytes: usize) {
+        self.downloaded_bytes
+            .fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    /// e.g. `Installed 12 package(s), 4 from the cache and 8 from the network, 1.2 MB
+    /// downloaded in 0.84s (resolution 0.21s)`.
+    pub fn summary(&self) -> String {
+        let cached = utils::lock(&self.cached).len();
+        let downloaded = self.downloaded.load(Ordering::Relaxed);
+        let resolved = utils::lock(&self.resolved).unwrap_or_default();
+
+        format!(
+            "Installed {} package(s), {} from the cache and {} from the network, {} downloaded in {:.2}s (resolution {:.2}s)",
+            cached + downloaded,
+            cached,
+            downloaded,
+            utils::format_size(self.downloaded_bytes.load(Ordering::Relaxed)),
+            self.started.elapsed().as_secs_f64(),
+            resolved.as_secs_f64()
+        )
+    }
+}
@@ -0,0 +1,83 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+
+/// The `key=value` entries of the `.npmrc` files, the project one taking precedence
+/// over the one in the home directory.
+pub struct Npmrc {
+    entries: HashMap<String, String>,
+}
+
+impl Npmrc {
+    pub fn load() -> Self {
+        let mut entries = HashMap::new();
+
+        let home_npmrc = dirs::home_dir().map(|home| home.join(".npmrc"));
+        for path in home_npmrc.into_iter().chain([".npmrc".into()]) {
+            let Ok(raw) = fs::read_to_string(path) else {
+                continue;
+            };
+
+            entries.extend(Self::parse(&raw));
+        }
+
+        Self { entries }
+    }
+
+    fn parse(raw: &str) -> HashMap<String, String> {
+        raw.lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#') && !line.starts_with(';'))
+            .filter_map(|line| line.split_once('='))
+            .map(|(key, value)| (key.trim().to_string(), Self::expand_env(value.trim())))
+            .collect()
+    }
+
+    /// Replaces `${VAR}` references with the value of the environment variable,
+    /// which is how tokens are usually kept out of `.npmrc` files.
+    fn expand_env(value: &str) -> String {
+        let mut expanded = String::new();
+        let mut rest = value;
+
+        while let Some(start) = rest.find("${") {
+            let Some(end) = rest[start..].find('}') else {
+                break;
+            };
+
+            expanded.push_str(&rest[..start]);
+            expanded.push_str(&env::var(&rest[start + 2..start + end]).unwrap_or_default());
+            rest = &rest[start + end + 1..];
+        }
+
+        expanded.push_str(rest);
+        expanded
+    }
+
+    pub fn get(&self, key: &str) -> Option<&String> {
+        self.entries.get(key)
+    }
+
+    /// The registry of the packages of a scope, set like `@myco:registry=https://npm.myco.com`.
+    pub fn scope_registry(&self, scope: &str) -> Option<&String> {
+        self.get(&format!("{scope}:registry"))
+    }
+
+    /// Every registry a scope is mapped to.
+    pub fn scope_registries(&self) -> impl Iterator<Item = &String> {
+        self.entries
+            .iter()
+            .filter(|(key, _)| key.starts_with('@') && key.ends_with(":registry"))
+            .map(|(_, registry)| registry)
+    }
+
+    /// Tokens are keyed by the registry URL without its scheme, e.g.
+    /// `//registry.npmjs.org/:_authToken=...`.
+    pub fn auth_token(&self, registry: &str) -> Option<&String> {
+        let without_scheme = registry
+            .split_once("//")
+            .map_or(registry, |(_, rest)| rest)
+            .trim_end_matches('/');
+
+        self.get(&format!("//{without_scheme}/:_authToken"))
+    }
+}
@@ -0,0 +1,211 @@
+use crate::errors::CommandError;
+use crate::versions::{PackageDetails, Versions};
+use log::warn;
+use serde::{de, Deserialize, Deserializer};
+use serde_json::{Map, Value};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+pub const PACKAGE_JSON: &str = "./package.json";
+
+#[derive(Deserialize)]
+pub struct PackageJson {
+    pub name: Option<String>,
+    pub version: Option<String>,
+    #[serde(default)]
+    pub dependencies: HashMap<String, String>,
+    #[serde(default, rename = "devDependencies")]
+    pub dev_dependencies: HashMap<String, String>,
+    #[serde(default)]
+    pub scripts: HashMap<String, String>,
+    /// The versions forced onto transitive dependencies, by package name. Only plain
+    /// `name: version` entries are supported, not npm's nested ones.
+    #[serde(default)]
+    pub overrides: Map<String, Value>,
+    /// The directories of the packages of a monorepo relative to its root, or patterns
+    /// matching them.
+    #[serde(default, deserialize_with = "deserialize_workspaces")]
+    pub workspaces: Vec<String>,
+}
+
+/// Reads `workspaces` as npm's array, or as Yarn's object listing them under `packages`.
+fn deserialize_workspaces<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let workspaces = match Value::deserialize(deserializer)? {
+        Value::Object(mut workspaces) => workspaces.remove("packages").unwrap_or_default(),
+        workspaces => workspaces,
+    };
+    serde_json::from_value(workspaces).map_err(de::Error::custom)
+}
+
+impl PackageJson {
+    pub fn read() -> Result<Self, CommandError> {
+        Self::read_from(Path::new(PACKAGE_JSON))
+    }
+
+    pub fn read_from(path: &Path) -> Result<Self, CommandError> {
+        let raw = fs::read_to_string(path).map_err(CommandError::NoPackageJson)?;
+        serde_json::from_str::<PackageJson>(&raw)
+            .map_err(|e| CommandError::InvalidPackageJson(path.display().to_string(), e))
+    }
+
+    /// Parses every entry of `dependencies`, and of `devDependencies` unless `production`
+    /// is set, into installable details.
+    pub fn all_dependencies(&self, production: bool) -> Result<Vec<PackageDetails>, CommandError> {
+        let dev_dependencies = match production {
+            true => None,
+            false => Some(&self.dev_dependencies),
+        };
+
+        self.dependencies
+            .iter()
+            .chain(dev_dependencies.into_iter().flatten())
+            .map(|(name, range)| {
+                Versions::parse_semantic_package_details(Versions::stringify(name, range))
+                    .map_err(CommandError::InvalidDependencyRange)
+            })
+            .collect()
+    }
+
+    /// The version each overridden package is forced to. A `$name` version references the
+    /// one the project itself depends on, and nested overrides are ignored with a warning.
+    pub fn overrides(&self) -> HashMap<String, String> {
+        self.overrides
+            .iter()
+            .filter_map(|(name, version)| {
+                let Value::String(version) = version else {
+                    warn!("Warning: ignoring the nested override of '{name}'");
+                    return None;
+                };
+
+                let version = match version.strip_prefix('$') {
+                    Some(reference) => self
+                        .dependencies
+                        .get(reference)
+                        .or(self.dev_dependencies.get(reference))?,
+                    None => version,
+                };
+                Some((name.to_string(), version.to_string()))
+            })
+            .collect()
+    }
+
+    /// The directories of the workspaces. A pattern such as `packages/*` matches the
+    /// directories holding a package.json, and one starting with `!`, e.g.
+    /// `!packages/legacy`, excludes the directories it matches.
+    pub fn workspace_dirs(&self) -> Result<Vec<PathBuf>, CommandError> {
+        let mut included = Vec::new();
+        let mut excluded = HashSet::new();
+        for workspace in self.workspaces.iter() {
+            let (negated, pattern) = match workspace.strip_prefix('!') {
+                Some(pattern) => (true, pattern),
+                None => (false, workspace.as_str()),
+            };
+
+            // a plain path is kept even when it doesn't exist, so that its absence is reported
+            let paths = match pattern.contains(['*', '?', '[']) {
+                false => vec![PathBuf::from(pattern)],
+                true => glob::glob(pattern)
+                    .map_err(|e| CommandError::InvalidWorkspacePattern(workspace.to_string(), e))?
+                    .filter_map(Result::ok)
+                    .filter(|path| path.join("package.json").is_file())
+                    .collect(),
+            };
+
+            match negated {
+                true => {
+                    excluded.extend(paths.iter().filter_map(|path| fs::canonicalize(path).ok()))
+                }
+                false => included.extend(paths),
+            }
+        }
+
+        // the same directory can be matched by several patterns
+        let mut seen = HashSet::new();
+        Ok(included
+            .into_iter()
+            .filter(|path| {
+                let directory = fs::canonicalize(path).unwrap_or(path.to_path_buf());
+                !excluded.contains(&directory) && seen.insert(directory)
+            })
+            .collect())
+    }
+
+    /// Records `name: range` under `dependencies`, or `devDependencies` when `dev` is set,
+    /// creating package.json if needed. A package saved in one field is removed from the
+    /// other. The manifest is edited as an ordered JSON document so unrelated keys keep their place.
+    pub fn save_dependency(name: &str, range: &str, dev: bool) -> Result<(), CommandError> {
+        let mut manifest = match fs::read_to_string(PACKAGE_JSON) {
+            Ok(raw) => serde_json::from_str::<Value>(&raw)
+                .map_err(|e| CommandError::InvalidPackageJson(String::from(PACKAGE_JSON), e))?,
+            Err(err) if err.kind() == ErrorKind::NotFound => Value::Object(Map::new()),
+            Err(err) => return Err(CommandError::NoPackageJson(err)),
+        };
+
+        let manifest = manifest
+            .as_object_mut()
+            .ok_or(CommandError::InvalidPackageJsonRoot)?;
+
+        let (field, other_field) = match dev {
+            true => ("devDependencies", "dependencies"),
+            false => ("dependencies", "devDependencies"),
+        };
+
+        if let Some(Value::Object(other)) = manifest.get_mut(other_field) {
+            other.shift_remove(name);
+        }
+
+        let dependencies = manifest.entry(field).or_insert(Value::Object(Map::new()));
+        if !dependencies.is_object() {
+            *dependencies = Value::Object(Map::new());
+        }
+
+        dependencies
+            .as_object_mut()
+            .expect("Dependencies should be an object")
+            .insert(name.to_string(), Value::String(range.to_string()));
+
+        let raw = serde_json::to_string_pretty(manifest)
+            .map_err(CommandError::FailedToSerializePackageJson)?;
+        fs::write(PACKAGE_JSON, format!("{raw}\n")).map_err(CommandError::FailedToWriteFile)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::process;
+
+    #[test]
+    fn reports_where_a_truncated_package_json_breaks() {
+        let path = env::temp_dir().join(format!("pie-truncated-{}.json", process::id()));
+        fs::write(
+            &path,
+            "{\n  \"name\": \"project\",\n  \"dependencies\": {\n    \"pkg\": ",
+        )
+        .unwrap();
+
+        let read = PackageJson::read_from(&path);
+        let _ = fs::remove_file(&path);
+
+        let message = read
+            .as_ref()
+            .err()
+            .map(|e| e.to_string())
+            .unwrap_or_default();
+        let Err(CommandError::InvalidPackageJson(file, e)) = read else {
+            panic!("a truncated package.json was read");
+        };
+        assert_eq!(file, path.display().to_string());
+        assert!(e.is_eof());
+        assert!(
+            message.contains(&file) && message.contains("line 4"),
+            "{message}"
+        );
+    }
+}
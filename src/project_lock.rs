@@ -0,0 +1,110 @@
+use crate::cache::Cache;
+use crate::errors::CommandError;
+use crate::package_json::PackageJson;
+use crate::types::{LockedPackage, ProjectLock, PROJECT_LOCK_VERSION};
+use crate::versions::{VersionRange, Versions};
+use semver::Version;
+use std::collections::HashMap;
+use std::fs;
+
+pub const PROJECT_LOCK: &str = "./pie-lock.json";
+
+pub type LockedVersions = HashMap<String, Vec<String>>;
+
+impl ProjectLock {
+    pub fn collect() -> Result<Self, CommandError> {
+        let linked = Cache::linked_packages()?;
+        let mut project_lock = Self::new();
+
+        let mut pending = linked.to_vec();
+        while let Some(package) = pending.pop() {
+            if project_lock.packages.contains_key(&package) {
+                continue;
+            }
+
+            // a dependency missing from the cache isn't installed, so it isn't locked either
+            let Ok(mut lock) = Cache::read_lock(&package) else {
+                continue;
+            };
+            lock.dependencies.sort();
+            pending.extend(lock.dependencies.iter().cloned());
+            project_lock.packages.insert(
+                package,
+                LockedPackage {
+                    integrity: lock.integrity,
+                    dependencies: lock.dependencies,
+                    requested: lock.requested,
+                },
+            );
+        }
+
+        // without a package.json, only the packages are recorded, and a linked version
+        // package.json doesn't allow anymore isn't recorded as the one the project depends on
+        if let Ok(package_json) = PackageJson::read() {
+            for (name, spec) in package_json.all_dependencies(false)? {
+                let linked_version = linked
+                    .iter()
+                    .map(|package| Versions::parse_raw_package_details(package.to_string()))
+                    .find(|(linked_name, _)| *linked_name == name)
+                    .filter(|(_, version)| {
+                        Version::parse(version).is_ok_and(|version| {
+                            spec.range().is_none_or(|range| range.matches(&version))
+                        })
+                    });
+
+                if let Some((name, version)) = linked_version {
+                    project_lock.dependencies.insert(name, version);
+                }
+            }
+        }
+
+        Ok(project_lock)
+    }
+
+    pub fn read() -> Result<Self, CommandError> {
+        let raw = fs::read_to_string(PROJECT_LOCK).map_err(CommandError::NoProjectLock)?;
+        let project_lock =
+            serde_json::from_str::<ProjectLock>(&raw).map_err(CommandError::ParsingFailed)?;
+
+        if project_lock.lockfile_version > PROJECT_LOCK_VERSION {
+            return Err(CommandError::UnsupportedLockfileVersion(
+                project_lock.lockfile_version,
+            ));
+        }
+
+        Ok(project_lock)
+    }
+
+    pub fn locked_versions(&self) -> LockedVersions {
+        let mut locked_versions = LockedVersions::new();
+
+        for package in self.packages.keys() {
+            let (name, version) = Versions::parse_raw_package_details(package.to_string());
+            locked_versions.entry(name).or_default().push(version);
+        }
+
+        locked_versions
+    }
+
+    /// The highest locked version of a package matching `range`. Without a range,
+    /// i.e. for a dist-tag, any locked version is accepted.
+    pub fn locked_version(
+        locked_versions: &LockedVersions,
+        name: &String,
+        range: Option<&VersionRange>,
+    ) -> Option<String> {
+        locked_versions
+            .get(name)?
+            .iter()
+            .filter_map(|version| Version::parse(version).ok())
+            .filter(|version| range.is_none_or(|range| range.matches(version)))
+            .max()
+            .map(|version| version.to_string())
+    }
+
+    pub fn write(&self) -> Result<(), CommandError> {
+        let raw = serde_json::to_string_pretty(self)
+            .map_err(CommandError::FailedToSerializePackageLock)?;
+        fs::write(PROJECT_LOCK, format!("{raw}\n")).map_err(CommandError::FailedToWriteFile)
+    }
+}
@@ -0,0 +1,93 @@
+use crate::errors::CommandError;
+use crate::types::Severity;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static JSON: AtomicBool = AtomicBool::new(false);
+
+#[derive(Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Source {
+    Cache,
+    Network,
+    Local,
+}
+
+/// The outcome of `pie install --json`.
+#[derive(Serialize)]
+pub struct InstallReport {
+    pub installed: Vec<InstalledPackage>,
+    pub failed: Vec<FailedPackage>,
+    /// Every package of the resolved tree by `name@version`, with its direct dependencies.
+    pub packages: BTreeMap<String, ResolvedPackage>,
+}
+
+#[derive(Serialize)]
+pub struct InstalledPackage {
+    pub name: String,
+    pub version: String,
+    pub source: Source,
+}
+
+#[derive(Serialize)]
+pub struct FailedPackage {
+    pub name: String,
+    pub error: String,
+}
+
+#[derive(Serialize)]
+pub struct ResolvedPackage {
+    pub source: Source,
+    pub dependencies: Vec<String>,
+}
+
+/// A package of `pie list --json`. A package already listed elsewhere in the tree is
+/// marked as deduped instead of repeating its dependencies.
+#[derive(Serialize)]
+pub struct TreeNode {
+    pub name: String,
+    pub version: String,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub deduped: bool,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub dependencies: Vec<TreeNode>,
+}
+
+/// A row of `pie outdated --json`, a version is `null` when it couldn't be found.
+#[derive(Serialize)]
+pub struct OutdatedPackage {
+    pub package: String,
+    pub current: Option<String>,
+    pub wanted: Option<String>,
+    pub latest: Option<String>,
+}
+
+/// A row of `pie audit --json`, one per vulnerable installed version and advisory.
+#[derive(Serialize)]
+pub struct Vulnerability {
+    pub package: String,
+    pub version: String,
+    pub severity: Severity,
+    pub title: String,
+    pub url: String,
+}
+
+pub struct Reporter;
+impl Reporter {
+    pub fn set_json(json: bool) {
+        JSON.store(json, Ordering::Relaxed);
+    }
+
+    /// Whether the commands print a JSON report instead of human readable text.
+    pub fn is_json() -> bool {
+        JSON.load(Ordering::Relaxed)
+    }
+
+    pub fn print<T: Serialize>(report: &T) -> Result<(), CommandError> {
+        let json =
+            serde_json::to_string_pretty(report).map_err(CommandError::FailedToSerializeReport)?;
+        println!("{json}");
+        Ok(())
+    }
+}
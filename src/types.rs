@@ -1,22 +1,252 @@
-use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use crate::utils;
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::str::FromStr;
 
+/// A packument, the abbreviated `install-v1` one or the full one some mirrors answer with.
 #[derive(Deserialize)]
 pub struct PackageData {
+    #[serde(default)]
     pub versions: HashMap<String, VersionData>,
+    #[serde(rename = "dist-tags", default)]
+    pub dist_tags: HashMap<String, String>,
+    pub modified: Option<String>,
+    #[serde(default, deserialize_with = "deserialize_times")]
+    pub time: HashMap<String, String>,
 }
 
-#[derive(Debug, Deserialize)]
+impl PackageData {
+    pub fn last_modified(&self) -> Option<&str> {
+        self.modified
+            .as_deref()
+            .or(self.time.get("modified").map(String::as_str))
+    }
+
+    pub fn published(&self, version: &str) -> Option<&str> {
+        self.time.get(version).map(String::as_str)
+    }
+}
+
+#[derive(Deserialize)]
+pub struct SearchResults {
+    pub objects: Vec<SearchResult>,
+    pub total: u64,
+}
+
+#[derive(Deserialize)]
+pub struct SearchResult {
+    pub package: SearchedPackage,
+}
+
+#[derive(Deserialize)]
+pub struct SearchedPackage {
+    pub name: String,
+    pub version: String,
+    pub description: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
 pub struct VersionData {
     pub name: String,
     pub version: String,
     pub dependencies: Option<HashMap<String, String>>,
+    #[serde(rename = "optionalDependencies")]
+    pub optional_dependencies: Option<HashMap<String, String>>,
+    #[serde(rename = "peerDependencies")]
+    pub peer_dependencies: Option<HashMap<String, String>>,
+    #[serde(default, deserialize_with = "deserialize_engines")]
+    pub engines: Option<HashMap<String, String>>,
+    pub bin: Option<Value>,
+    /// The dependencies shipped in the tarball, npm accepts both spellings.
+    #[serde(rename = "bundledDependencies")]
+    pub bundled_dependencies: Option<Value>,
+    #[serde(rename = "bundleDependencies")]
+    pub bundle_dependencies: Option<Value>,
+    #[serde(default, deserialize_with = "deserialize_deprecated")]
+    pub deprecated: Option<String>,
+    /// In npm's naming, an entry starting with `!` excludes a platform.
+    #[serde(default, deserialize_with = "deserialize_platforms")]
+    pub os: Option<Vec<String>>,
+    #[serde(default, deserialize_with = "deserialize_platforms")]
+    pub cpu: Option<Vec<String>>,
+    #[serde(default)]
     pub dist: Dist,
 }
 
-#[derive(Debug, Deserialize)]
+impl VersionData {
+    pub fn supports_platform(&self) -> bool {
+        Self::allows(&self.os, utils::npm_os()) && Self::allows(&self.cpu, utils::npm_cpu())
+    }
+
+    fn allows(platforms: &Option<Vec<String>>, host: &str) -> bool {
+        let Some(platforms) = platforms.as_ref().filter(|platforms| !platforms.is_empty()) else {
+            return true;
+        };
+
+        let (excluded, included): (Vec<_>, Vec<_>) = platforms
+            .iter()
+            .partition(|platform| platform.starts_with('!'));
+        if excluded.iter().any(|platform| &platform[1..] == host) {
+            return false;
+        }
+
+        included.is_empty() || included.iter().any(|platform| *platform == host)
+    }
+
+    /// The executables of the package by command name. A single `bin` string is named after
+    /// the package, without its scope.
+    pub fn bins(&self) -> HashMap<String, String> {
+        match &self.bin {
+            Some(Value::String(path)) => {
+                let command = self.name.rsplit('/').next().unwrap_or(&self.name);
+                HashMap::from([(command.to_string(), path.to_string())])
+            }
+            Some(Value::Object(bins)) => bins
+                .iter()
+                .filter_map(|(command, path)| {
+                    Some((command.to_string(), path.as_str()?.to_string()))
+                })
+                .collect(),
+            _ => HashMap::new(),
+        }
+    }
+
+    /// `true` bundles every dependency.
+    pub fn bundled(&self) -> HashSet<String> {
+        let bundled = self
+            .bundled_dependencies
+            .as_ref()
+            .or(self.bundle_dependencies.as_ref());
+
+        match bundled {
+            Some(Value::Array(names)) => names
+                .iter()
+                .filter_map(|name| Some(name.as_str()?.to_string()))
+                .collect(),
+            Some(Value::Bool(true)) => self
+                .dependencies
+                .iter()
+                .flatten()
+                .chain(self.optional_dependencies.iter().flatten())
+                .map(|(name, _)| name.to_string())
+                .collect(),
+            _ => HashSet::new(),
+        }
+    }
+}
+
+/// Some old packages declare `engines` as an array of strings, those are ignored.
+fn deserialize_engines<'de, D>(deserializer: D) -> Result<Option<HashMap<String, String>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let engines = Value::deserialize(deserializer)?;
+    Ok(serde_json::from_value(engines).ok())
+}
+
+// some packages publish `"deprecated": false`, which isn't a deprecation
+fn deserialize_deprecated<'de, D>(deserializer: D) -> Result<Option<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let deprecated = Value::deserialize(deserializer)?;
+    Ok(deprecated.as_str().map(String::from))
+}
+
+// a single platform is sometimes published as a string
+fn deserialize_platforms<'de, D>(deserializer: D) -> Result<Option<Vec<String>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match Value::deserialize(deserializer)? {
+        Value::String(platform) => Ok(Some(vec![platform])),
+        platforms => Ok(serde_json::from_value(platforms).ok()),
+    }
+}
+
+/// Keeps the dates of `time`, dropping the other entries such as the `unpublished` object
+/// of a package whose versions were all unpublished.
+fn deserialize_times<'de, D>(deserializer: D) -> Result<HashMap<String, String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match Value::deserialize(deserializer)? {
+        Value::Object(times) => Ok(times
+            .into_iter()
+            .filter_map(|(key, time)| match time {
+                Value::String(time) => Some((key, time)),
+                _ => None,
+            })
+            .collect()),
+        _ => Ok(HashMap::new()),
+    }
+}
+
+/// Some registries leave the tarball out, e.g. for deprecated versions.
+#[derive(Clone, Debug, Default, Deserialize)]
 pub struct Dist {
+    #[serde(default)]
     pub tarball: String,
+    #[serde(default)]
+    pub shasum: String,
+    pub integrity: Option<String>,
+}
+
+/// From the least to the most severe.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Info,
+    #[default]
+    Low,
+    Moderate,
+    High,
+    Critical,
+}
+
+impl Severity {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Info => "info",
+            Self::Low => "low",
+            Self::Moderate => "moderate",
+            Self::High => "high",
+            Self::Critical => "critical",
+        }
+    }
+}
+
+impl FromStr for Severity {
+    type Err = ();
+
+    fn from_str(severity: &str) -> Result<Self, Self::Err> {
+        match severity {
+            "info" => Ok(Self::Info),
+            "low" => Ok(Self::Low),
+            "moderate" => Ok(Self::Moderate),
+            "high" => Ok(Self::High),
+            "critical" => Ok(Self::Critical),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct Advisory {
+    #[serde(default)]
+    pub title: String,
+    #[serde(default)]
+    pub url: String,
+    pub severity: Severity,
+    #[serde(default)]
+    pub vulnerable_versions: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct CachedMetadata {
+    pub etag: String,
+    pub body: String,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -24,6 +254,27 @@ pub struct PackageLock {
     #[serde(rename = "isLatest")]
     pub is_latest: bool,
     pub dependencies: Vec<String>,
+    /// The direct dependencies by name, a lock written before they were recorded only has
+    /// the flat `dependencies`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub requested: BTreeMap<String, RequestedDependency>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub bin: HashMap<String, String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub integrity: Option<String>,
+    /// The hash of the extracted files, once the postinstall script ran.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub contents: Option<String>,
+    /// The overrides of the project that applied to the dependencies, which are resolved
+    /// again under other ones.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub overrides: BTreeMap<String, String>,
+    #[serde(
+        default,
+        rename = "peerDependencies",
+        skip_serializing_if = "HashMap::is_empty"
+    )]
+    pub peer_dependencies: HashMap<String, String>,
 }
 
 impl PackageLock {
@@ -31,8 +282,51 @@ impl PackageLock {
         Self {
             is_latest,
             dependencies: Vec::new(),
+            requested: BTreeMap::new(),
+            bin: HashMap::new(),
+            integrity: None,
+            contents: None,
+            overrides: BTreeMap::new(),
+            peer_dependencies: HashMap::new(),
         }
     }
 }
 
+#[derive(Clone, Serialize, Deserialize)]
+pub struct RequestedDependency {
+    pub range: String,
+    pub version: String,
+}
+
 pub type DependencyMap = HashMap<String, PackageLock>;
+
+/// Version 2 records the requested dependencies of each package, version 1 lockfiles are
+/// still read.
+pub const PROJECT_LOCK_VERSION: u32 = 2;
+
+#[derive(Serialize, Deserialize)]
+pub struct ProjectLock {
+    #[serde(rename = "lockfileVersion")]
+    pub lockfile_version: u32,
+    pub dependencies: BTreeMap<String, String>,
+    pub packages: BTreeMap<String, LockedPackage>,
+}
+
+impl ProjectLock {
+    pub fn new() -> Self {
+        Self {
+            lockfile_version: PROJECT_LOCK_VERSION,
+            dependencies: BTreeMap::new(),
+            packages: BTreeMap::new(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct LockedPackage {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub integrity: Option<String>,
+    pub dependencies: Vec<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub requested: BTreeMap<String, RequestedDependency>,
+}
@@ -1,34 +1,556 @@
 use crate::errors::CommandError;
-use bytes::Bytes;
+use crate::types::Dist;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use bytes::{Buf, Bytes};
 use flate2::bufread::GzDecoder;
-use semver::{BuildMetadata, Prerelease, Version};
+use flate2::read::GzDecoder as StreamGzDecoder;
+use futures::FutureExt;
+use lazy_static::lazy_static;
+use log::warn;
+use sha1::{Digest, Sha1};
+use sha2::Sha512;
+use std::any::Any;
+use std::env;
+use std::fs;
 use std::future::Future;
-use std::path::Path;
+use std::io::{self, Read, Write};
+use std::mem;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Component, Path, PathBuf};
+use std::process;
 use std::sync::atomic::AtomicUsize;
-use tar::Archive;
+use std::sync::mpsc::Receiver;
+use std::sync::{Mutex, MutexGuard};
+use tar::{Archive, EntryType};
 use tokio::task::JoinHandle;
 
 pub const REGISTRY_URL: &str = "https://registry.npmjs.org";
 
-pub const EMPTY_VERSION: Version = Version {
-    major: 0,
-    minor: 0,
-    patch: 0,
-    pre: Prerelease::EMPTY,
-    build: BuildMetadata::EMPTY,
-};
-
 pub const LATEST: &str = "latest";
 
+/// The prefix of the directories packages are extracted to before being moved into the cache,
+/// and of their partial downloads.
+pub const TEMP_PREFIX: &str = ".tmp-";
+
+/// How long a partial download stays untouched before another process takes it over, the
+/// process downloading it writes to it continually.
+const ABANDONED_DOWNLOAD_AGE: std::time::Duration = std::time::Duration::from_secs(60);
+
+lazy_static! {
+    /// Where `install --global` puts its packages, with their executables in a `bin` directory.
+    pub static ref GLOBAL_DIR: String = format!(
+        "{}/pie/global",
+        dirs::data_dir()
+            .expect("Could not find data directory")
+            .to_str()
+            .expect("Couldn't convert data directory path to string")
+    );
+}
+
+/// The operating system of this machine, as the `os` of the packages names it.
+pub fn npm_os() -> &'static str {
+    match env::consts::OS {
+        "macos" => "darwin",
+        "windows" => "win32",
+        "solaris" | "illumos" => "sunos",
+        os => os,
+    }
+}
+
+/// The CPU architecture of this machine, as the `cpu` of the packages names it.
+pub fn npm_cpu() -> &'static str {
+    match env::consts::ARCH {
+        "x86_64" => "x64",
+        "x86" => "ia32",
+        "aarch64" => "arm64",
+        "powerpc" => "ppc",
+        "powerpc64" => "ppc64",
+        "loongarch64" => "loong64",
+        arch => arch,
+    }
+}
+
 pub fn extract_tarball(bytes: Bytes, destination: String) -> Result<(), CommandError> {
     let bytes = &bytes.to_vec()[..];
     let gz = GzDecoder::new(bytes);
     let mut archive = Archive::new(gz);
 
-    archive
-        .unpack(&destination)
-        .map_err(CommandError::ExtractionFailed)
-        .expect("Failed to extract tarball");
+    let temp = temp_destination(&destination);
+    let result = unpack(&mut archive, &temp).and_then(|_| move_into_place(&temp, &destination));
+    if result.is_err() {
+        let _ = fs::remove_dir_all(&temp);
+    }
+
+    result
+}
+
+/// Where a package is extracted before being moved into place, a dot directory next to its
+/// cache entry that the cache lookups ignore. A crash then leaves it behind instead of a
+/// partial cache entry.
+fn temp_destination(destination: &str) -> String {
+    let destination = Path::new(destination);
+    let name = destination
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+
+    destination
+        .with_file_name(format!("{TEMP_PREFIX}{}-{name}", process::id()))
+        .to_string_lossy()
+        .to_string()
+}
+
+/// A tarball being streamed, kept next to the extraction directory of its package so that a
+/// download cut short by a crash is resumed by the next install. Failing to write it only
+/// loses that.
+pub struct PartialDownload {
+    path: PathBuf,
+    file: Option<fs::File>,
+}
+
+impl PartialDownload {
+    /// The partial download of a cache entry, taking over one that another process left
+    /// untouched for a while.
+    pub fn claim(destination: &str) -> Self {
+        let path = PathBuf::from(format!("{}.tgz", temp_destination(destination)));
+        let destination = Path::new(destination);
+        let (Some(dir), Some(name)) = (destination.parent(), destination.file_name()) else {
+            return Self { path, file: None };
+        };
+
+        let suffix = format!("-{}.tgz", name.to_string_lossy());
+        let leftover = fs::read_dir(dir)
+            .into_iter()
+            .flatten()
+            .filter_map(Result::ok)
+            .find(|entry| {
+                let filename = entry.file_name().to_string_lossy().to_string();
+                let is_partial = filename
+                    .strip_prefix(TEMP_PREFIX)
+                    .and_then(|rest| rest.strip_suffix(&suffix))
+                    .is_some_and(|pid| !pid.is_empty() && pid.chars().all(|c| c.is_ascii_digit()));
+                let is_abandoned = entry
+                    .metadata()
+                    .and_then(|metadata| metadata.modified())
+                    .is_ok_and(|modified| {
+                        modified
+                            .elapsed()
+                            .is_ok_and(|elapsed| elapsed > ABANDONED_DOWNLOAD_AGE)
+                    });
+                is_partial && is_abandoned && entry.path() != path
+            });
+        if let Some(leftover) = leftover {
+            let _ = fs::rename(leftover.path(), &path);
+        }
+
+        Self { path, file: None }
+    }
+
+    /// How many bytes were downloaded before.
+    pub fn downloaded(&self) -> u64 {
+        fs::metadata(&self.path).map_or(0, |metadata| metadata.len())
+    }
+
+    pub fn read(&self) -> Result<Bytes, CommandError> {
+        fs::read(&self.path)
+            .map(Bytes::from)
+            .map_err(CommandError::FailedToReadFile)
+    }
+
+    /// Starts writing the download, after the bytes downloaded before when it is resumed, or
+    /// over them otherwise.
+    pub fn start(&mut self, resumed: bool) {
+        if let Some(dir) = self.path.parent() {
+            let _ = fs::create_dir_all(dir);
+        }
+        self.file = fs::OpenOptions::new()
+            .create(true)
+            .write(true)
+            .append(resumed)
+            .truncate(!resumed)
+            .open(&self.path)
+            .ok();
+    }
+
+    pub fn append(&mut self, chunk: &[u8]) {
+        let Some(file) = self.file.as_mut() else {
+            return;
+        };
+        if file.write_all(chunk).is_err() {
+            self.file = None;
+        }
+    }
+
+    /// Removes the download once it is complete.
+    pub fn remove(self) {
+        drop(self.file);
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Renames a fully extracted package to its cache entry, replacing what an older version of
+/// pie or a forced install left there. The old entry is moved aside before being removed, so
+/// that the entry is never left half removed.
+fn move_into_place(temp: &str, destination: &str) -> Result<(), CommandError> {
+    if Path::new(destination).exists() {
+        let replaced = format!("{temp}-replaced");
+        fs::rename(destination, &replaced).map_err(CommandError::ExtractionFailed)?;
+        fs::rename(temp, destination).map_err(CommandError::ExtractionFailed)?;
+        // a leftover is a temporary directory, removed by the next cache cleanup
+        let _ = fs::remove_dir_all(&replaced);
+        return Ok(());
+    }
+
+    fs::rename(temp, destination).map_err(CommandError::ExtractionFailed)
+}
+
+/// Whether a relative path would lead out of the directory it is resolved from.
+fn escapes(path: &Path) -> bool {
+    let mut depth = 0usize;
+
+    for component in path.components() {
+        match component {
+            Component::Normal(_) => depth += 1,
+            Component::CurDir => {}
+            Component::ParentDir if depth > 0 => depth -= 1,
+            Component::ParentDir | Component::RootDir | Component::Prefix(_) => return true,
+        }
+    }
+
+    false
+}
+
+/// Unpacks the entries of an archive one by one, refusing any entry, or link target, that
+/// would end up outside of `destination`, as a crafted tarball could otherwise overwrite any file.
+fn unpack<R: Read>(archive: &mut Archive<R>, destination: &str) -> Result<(), CommandError> {
+    fs::create_dir_all(destination).map_err(CommandError::FailedToCreateDir)?;
+
+    for entry in archive.entries().map_err(CommandError::ExtractionFailed)? {
+        let mut entry = entry.map_err(CommandError::ExtractionFailed)?;
+        let path = entry
+            .path()
+            .map_err(CommandError::ExtractionFailed)?
+            .to_path_buf();
+
+        let link_escapes = match (entry.header().entry_type(), entry.link_name()) {
+            // a symbolic link is resolved from its own directory, a hard link from the archive root
+            (EntryType::Symlink, Ok(Some(target))) => {
+                escapes(&path.parent().unwrap_or(Path::new("")).join(target))
+            }
+            (EntryType::Link, Ok(Some(target))) => escapes(&target),
+            (_, Err(e)) => return Err(CommandError::ExtractionFailed(e)),
+            _ => false,
+        };
+
+        if escapes(&path) || link_escapes {
+            return Err(CommandError::UnsafeTarballEntry(
+                path.to_string_lossy().to_string(),
+            ));
+        }
+
+        entry
+            .unpack_in(destination)
+            .map_err(CommandError::ExtractionFailed)?;
+    }
+
+    Ok(())
+}
+
+/// Reads the `package.json` at the root of a package tarball, which npm puts in a `package`
+/// directory, though some tarballs name it otherwise.
+pub fn read_tarball_package_json(bytes: &Bytes, tarball: &str) -> Result<String, CommandError> {
+    let mut archive = Archive::new(GzDecoder::new(&bytes[..]));
+
+    for entry in archive.entries().map_err(CommandError::ExtractionFailed)? {
+        let mut entry = entry.map_err(CommandError::ExtractionFailed)?;
+        let path = entry.path().map_err(CommandError::ExtractionFailed)?;
+
+        if path.components().count() == 2 && path.ends_with("package.json") {
+            let mut raw = String::new();
+            entry
+                .read_to_string(&mut raw)
+                .map_err(CommandError::ExtractionFailed)?;
+            return Ok(raw);
+        }
+    }
+
+    Err(CommandError::MissingTarballPackageJson(tarball.to_string()))
+}
+
+/// Extracts a tarball as its chunks are downloaded, so that it is never held in memory
+/// as a whole. The chunks are hashed on the way and the extracted files are removed
+/// if they don't match the registry checksums.
+pub fn extract_from_stream(
+    chunks: Receiver<Bytes>,
+    dist: &Dist,
+    package: &str,
+    destination: &str,
+) -> Result<(), CommandError> {
+    let temp = temp_destination(destination);
+    let mut archive = Archive::new(StreamGzDecoder::new(ChunkReader::new(chunks)));
+    let unpacked = unpack(&mut archive, &temp);
+
+    // the archive can end before the gzip trailer, which still has to be hashed
+    let mut reader = archive.into_inner().into_inner();
+    let result = unpacked
+        .and_then(|_| {
+            io::copy(&mut reader, &mut io::sink()).map_err(CommandError::ExtractionFailed)
+        })
+        .and_then(|_| reader.hasher.verify(dist, package))
+        .and_then(|_| move_into_place(&temp, destination));
+    if result.is_err() {
+        let _ = fs::remove_dir_all(&temp);
+    }
+
+    result
+}
+
+/// Reads the chunks of a download as they are received, hashing them along the way.
+struct ChunkReader {
+    chunks: Receiver<Bytes>,
+    current: Bytes,
+    hasher: IntegrityHasher,
+}
+
+impl ChunkReader {
+    fn new(chunks: Receiver<Bytes>) -> Self {
+        Self {
+            chunks,
+            current: Bytes::new(),
+            hasher: IntegrityHasher::default(),
+        }
+    }
+}
+
+impl Read for ChunkReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.current.is_empty() {
+            // the download is over once the sender is dropped
+            match self.chunks.recv() {
+                Ok(chunk) => {
+                    self.hasher.update(&chunk);
+                    self.current = chunk;
+                }
+                Err(_) => return Ok(0),
+            }
+        }
+
+        let len = buf.len().min(self.current.len());
+        self.current.copy_to_slice(&mut buf[..len]);
+        Ok(len)
+    }
+}
+
+#[derive(Default)]
+struct IntegrityHasher {
+    sha1: Sha1,
+    sha512: Sha512,
+}
+
+impl IntegrityHasher {
+    fn update(&mut self, bytes: &[u8]) {
+        self.sha1.update(bytes);
+        self.sha512.update(bytes);
+    }
+
+    /// Checks the hashed bytes against the `shasum` and the sha512 `integrity` of the registry.
+    fn verify(self, dist: &Dist, package: &str) -> Result<(), CommandError> {
+        let mismatch = || CommandError::IntegrityMismatch(package.to_string());
+
+        if !dist.shasum.is_empty() {
+            let shasum = self
+                .sha1
+                .finalize()
+                .iter()
+                .map(|byte| format!("{byte:02x}"))
+                .collect::<String>();
+
+            if !shasum.eq_ignore_ascii_case(&dist.shasum) {
+                return Err(mismatch());
+            }
+        }
+
+        // an SRI string may hold several `algorithm-base64` hashes separated by spaces
+        let sha512_hashes = dist
+            .integrity
+            .iter()
+            .flat_map(|integrity| integrity.split_whitespace())
+            .filter_map(|hash| hash.strip_prefix("sha512-"))
+            .collect::<Vec<_>>();
+
+        if !sha512_hashes.is_empty() {
+            let digest = STANDARD.encode(self.sha512.finalize());
+
+            if !sha512_hashes.contains(&digest.as_str()) {
+                return Err(mismatch());
+            }
+        }
+
+        Ok(())
+    }
+}
+
+pub fn verify_integrity(bytes: &Bytes, dist: &Dist, package: &str) -> Result<(), CommandError> {
+    let mut hasher = IntegrityHasher::default();
+    hasher.update(bytes);
+    hasher.verify(dist, package)
+}
+
+/// Total size of the files under `path`. Symlinks are not followed.
+pub fn dir_size(path: &Path) -> u64 {
+    let Ok(metadata) = fs::symlink_metadata(path) else {
+        return 0;
+    };
+
+    if !metadata.is_dir() {
+        return metadata.len();
+    }
+
+    fs::read_dir(path)
+        .map(|entries| {
+            entries
+                .filter_map(Result::ok)
+                .map(|entry| dir_size(&entry.path()))
+                .sum()
+        })
+        .unwrap_or(0)
+}
+
+/// A hash of the files of a directory, of their relative paths and contents, in the SRI
+/// format of the registry. The files of its root named in `excluded` are left out.
+pub fn hash_dir(dir: &Path, excluded: &[&str]) -> io::Result<String> {
+    let mut hasher = Sha512::new();
+    hash_tree(dir, Path::new(""), excluded, &mut hasher)?;
+    Ok(format!("sha512-{}", STANDARD.encode(hasher.finalize())))
+}
+
+fn hash_tree(
+    dir: &Path,
+    relative: &Path,
+    excluded: &[&str],
+    hasher: &mut Sha512,
+) -> io::Result<()> {
+    // sorted, as the order of the entries of a directory depends on the filesystem
+    let mut entries = fs::read_dir(dir)?.collect::<io::Result<Vec<_>>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+
+    for entry in entries {
+        let name = entry.file_name();
+        if relative.as_os_str().is_empty() && excluded.iter().any(|excluded| name == *excluded) {
+            continue;
+        }
+
+        let path = relative.join(&name);
+        hasher.update(path.to_string_lossy().replace('\\', "/").as_bytes());
+        hasher.update([0]);
+
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            hash_tree(&entry.path(), &path, excluded, hasher)?;
+        } else if file_type.is_symlink() {
+            hasher.update(fs::read_link(entry.path())?.to_string_lossy().as_bytes());
+        } else {
+            io::copy(&mut fs::File::open(entry.path())?, hasher)?;
+        }
+        hasher.update([0]);
+    }
+
+    Ok(())
+}
+
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KB", "MB", "GB"];
+
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+
+    match unit {
+        0 => format!("{bytes} B"),
+        _ => format!("{size:.1} {}", UNITS[unit]),
+    }
+}
+
+/// Prints rows as columns aligned on their widest cell.
+pub fn print_table<const N: usize>(rows: &[[String; N]]) {
+    let mut widths = [0; N];
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row.iter()) {
+            *width = (*width).max(cell.len());
+        }
+    }
+
+    for row in rows {
+        let line = row
+            .iter()
+            .zip(widths.iter())
+            .map(|(cell, width)| format!("{cell:<width$}"))
+            .collect::<Vec<_>>();
+        println!("{}", line.join("  ").trim_end());
+    }
+}
+
+/// Links a package directory into `node_modules`. Windows only lets administrators create
+/// symbolic links, so a junction is created there instead, or else a copy.
+#[cfg(not(windows))]
+pub fn link_dir(target: &Path, link: &Path) -> io::Result<()> {
+    symlink::symlink_dir(target, link)
+}
+
+#[cfg(windows)]
+pub fn link_dir(target: &Path, link: &Path) -> io::Result<()> {
+    match junction::create(target, link) {
+        Ok(_) => Ok(()),
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => Err(e),
+        Err(_) => {
+            // a failed junction can leave its empty directory behind
+            let _ = fs::remove_dir(link);
+            copy_dir(target, link)
+        }
+    }
+}
+
+/// Removes a package from `node_modules`, whether it was linked or copied.
+pub fn unlink_dir(link: &Path) -> io::Result<()> {
+    match fs::symlink_metadata(link)?.file_type().is_symlink() {
+        true => symlink::remove_symlink_dir(link),
+        false => fs::remove_dir_all(link),
+    }
+}
+
+/// Copies a directory recursively, failing with `AlreadyExists` when `to` exists, like a link would.
+pub fn copy_dir(from: &Path, to: &Path) -> io::Result<()> {
+    copy_tree(from, to, &|from, to| fs::copy(from, to).map(|_| ()))
+}
+
+/// Recreates the directories of `from` in `to` and hard links each of its files there, so
+/// they take no extra space. A file that can't be hard linked, e.g. because `to` is on
+/// another filesystem, is copied instead.
+pub fn hardlink_dir(from: &Path, to: &Path) -> io::Result<()> {
+    copy_tree(from, to, &|from, to| {
+        fs::hard_link(from, to).or_else(|_| fs::copy(from, to).map(|_| ()))
+    })
+}
+
+fn copy_tree(
+    from: &Path,
+    to: &Path,
+    copy_file: &dyn Fn(&Path, &Path) -> io::Result<()>,
+) -> io::Result<()> {
+    fs::create_dir(to)?;
+
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let destination = to.join(entry.file_name());
+        match entry.file_type()?.is_dir() {
+            true => copy_tree(&entry.path(), &destination, copy_file)?,
+            false => copy_file(&entry.path(), &destination)?,
+        }
+    }
 
     Ok(())
 }
@@ -43,19 +565,45 @@ pub fn create_node_modules_dir() {
 
 pub static ACTIVE_TASKS: AtomicUsize = AtomicUsize::new(0);
 
+lazy_static! {
+    /// Why spawned tasks failed, kept until `TaskAllocator::take_errors` is called.
+    static ref TASK_ERRORS: Mutex<Vec<CommandError>> = Mutex::new(Vec::new());
+}
+
+/// Keeps a task counted as active until it is dropped, even if the task panics.
+struct ActiveTask;
+impl Drop for ActiveTask {
+    fn drop(&mut self) {
+        TaskAllocator::decrement_tasks();
+    }
+}
+
+/// Locks a mutex shared by the tasks. One that a panicking task left poisoned is recovered,
+/// the panic is already recorded and failing the install, the other tasks can go on.
+pub fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|poisoned| {
+        warn!("A task panicked while holding a lock, carrying on with the state it left");
+        mutex.clear_poison();
+        poisoned.into_inner()
+    })
+}
+
 pub struct TaskAllocator;
 impl TaskAllocator {
+    // tasks are counted before being spawned, otherwise `block_until_done` could return
+    // before a freshly spawned task had the chance to run
     pub fn add_task<T>(future: T) -> JoinHandle<T::Output>
     where
         T: Future + Send + 'static,
         T::Output: Send + 'static,
     {
+        let active_task = Self::increment_tasks();
         tokio::spawn(async move {
-            Self::increment_tasks();
-            let task_result = future.await;
-            Self::decrement_tasks();
-
-            task_result
+            let _active_task = active_task;
+            match AssertUnwindSafe(future).catch_unwind().await {
+                Ok(output) => output,
+                Err(panic) => Self::record_panic(panic),
+            }
         })
     }
 
@@ -64,23 +612,56 @@ impl TaskAllocator {
         F: FnOnce() -> R + Send + 'static,
         R: Send + 'static,
     {
+        let active_task = Self::increment_tasks();
         tokio::task::spawn_blocking(move || {
-            Self::increment_tasks();
-            let task_result = f();
-            Self::decrement_tasks();
-
-            task_result
+            let _active_task = active_task;
+            match panic::catch_unwind(AssertUnwindSafe(f)) {
+                Ok(output) => output,
+                Err(panic) => Self::record_panic(panic),
+            }
         })
     }
 
-    pub fn block_until_done() {
+    /// Records a panicking task so that it isn't lost when nobody awaits it, and carries on
+    /// panicking for those who do.
+    fn record_panic(panic: Box<dyn Any + Send>) -> ! {
+        let message = panic
+            .downcast_ref::<&str>()
+            .map(|message| message.to_string())
+            .or_else(|| panic.downcast_ref::<String>().cloned())
+            .unwrap_or(String::from("unknown reason"));
+        Self::record_error(CommandError::TaskPanicked(message));
+        panic::resume_unwind(panic)
+    }
+
+    fn record_error(e: CommandError) {
+        lock(&TASK_ERRORS).push(e);
+    }
+
+    /// The errors of the tasks that failed since the last call, to be checked once
+    /// `block_until_done` returns.
+    pub fn take_errors() -> Vec<CommandError> {
+        mem::take(&mut *lock(&TASK_ERRORS))
+    }
+
+    /// Waits for every task to be done, for at most `deadline`.
+    pub fn block_until_done(deadline: std::time::Duration) -> Result<(), CommandError> {
+        let start = std::time::Instant::now();
         while Self::active_tasks() != 0 {
+            if start.elapsed() >= deadline {
+                return Err(CommandError::InstallStuck(
+                    Self::active_tasks(),
+                    deadline.as_secs(),
+                ));
+            }
             std::thread::sleep(std::time::Duration::from_millis(1));
         }
+        Ok(())
     }
 
-    fn increment_tasks() {
+    fn increment_tasks() -> ActiveTask {
         ACTIVE_TASKS.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        ActiveTask
     }
 
     fn decrement_tasks() {
@@ -91,3 +672,84 @@ impl TaskAllocator {
         ACTIVE_TASKS.load(std::sync::atomic::Ordering::SeqCst)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+
+    /// A gzipped tarball with the given entries, their paths written as is, as `tar::Builder`
+    /// refuses the unsafe ones.
+    fn tarball(entries: &[(&str, &[u8])]) -> Bytes {
+        let mut builder = tar::Builder::new(GzEncoder::new(Vec::new(), Compression::default()));
+        for (path, contents) in entries {
+            let mut header = tar::Header::new_old();
+            header.as_old_mut().name[..path.len()].copy_from_slice(path.as_bytes());
+            header.set_size(contents.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder.append(&header, *contents).unwrap();
+        }
+        Bytes::from(builder.into_inner().unwrap().finish().unwrap())
+    }
+
+    fn test_dir(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("pie-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn refuses_tarball_entries_escaping_the_destination() {
+        let dir = test_dir("traversal");
+        let destination = dir.join("cache/pkg@1.0.0");
+        let outside = dir.join("escaped.txt");
+
+        for path in ["package/../../../escaped.txt", outside.to_str().unwrap()] {
+            let bytes = tarball(&[("package/index.js", b"ok"), (path, b"escaped")]);
+            let extracted = extract_tarball(bytes, destination.to_string_lossy().to_string());
+
+            assert!(
+                matches!(extracted, Err(CommandError::UnsafeTarballEntry(_))),
+                "{path}"
+            );
+            assert!(!outside.exists(), "{path}");
+            assert!(!destination.exists(), "{path}");
+        }
+
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[tokio::test]
+    async fn gives_up_on_a_task_that_never_finishes() {
+        let stuck = TaskAllocator::add_task(futures::future::pending::<()>());
+
+        let waited = TaskAllocator::block_until_done(std::time::Duration::from_millis(50));
+
+        assert!(matches!(waited, Err(CommandError::InstallStuck(..))));
+        stuck.abort();
+    }
+
+    #[tokio::test]
+    async fn recovers_the_lock_a_panicking_task_poisoned() {
+        let shared = std::sync::Arc::new(Mutex::new(Vec::new()));
+        let poisoning = shared.clone();
+        let task = TaskAllocator::add_blocking_task(move || {
+            let mut state = lock(&poisoning);
+            state.push(1);
+            panic!("induced panic");
+        });
+
+        assert!(task.await.is_err());
+        assert!(shared.is_poisoned());
+        lock(&shared).push(2);
+        assert_eq!(*lock(&shared), vec![1, 2]);
+        assert!(!shared.is_poisoned());
+        assert!(TaskAllocator::take_errors().iter().any(|e| matches!(
+            e,
+            CommandError::TaskPanicked(message) if message == "induced panic"
+        )));
+    }
+}
@@ -1,58 +1,288 @@
 use crate::errors::{CommandError, ParseError};
-use crate::types::VersionData;
-use crate::utils::{EMPTY_VERSION, LATEST};
-use semver::{Comparator, Op, Version, VersionReq};
-use std::collections::HashMap;
-use std::str::FromStr;
+use crate::types::{PackageData, VersionData};
+use crate::utils::LATEST;
+use semver::{Op, Version, VersionReq};
+use std::fmt::{Display, Formatter};
 
-type PackageDetails = (String, Option<Comparator>);
+pub type PackageDetails = (String, VersionSpec);
+
+const MAX_PACKAGE_NAME_LENGTH: usize = 214;
+
+/// What was asked for after the `@` of a package: a dist-tag such as `latest` or `next`,
+/// or a version range.
+#[derive(Clone, Debug)]
+pub enum VersionSpec {
+    Tag(String),
+    Range(VersionRange),
+    /// The URL of a tarball, the package is then not resolved through the registry.
+    Tarball(String),
+    /// The path to a local directory or tarball, from a `file:` spec or a `./` path.
+    Local(String),
+}
+
+impl VersionSpec {
+    pub fn range(&self) -> Option<&VersionRange> {
+        match self {
+            VersionSpec::Tag(_) | VersionSpec::Tarball(_) | VersionSpec::Local(_) => None,
+            VersionSpec::Range(range) => Some(range),
+        }
+    }
+}
+
+impl Display for VersionSpec {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VersionSpec::Tag(tag) | VersionSpec::Tarball(tag) => write!(f, "{tag}"),
+            VersionSpec::Local(path) => write!(f, "file:{path}"),
+            VersionSpec::Range(range) => write!(f, "{range}"),
+        }
+    }
+}
+
+/// A version range as written in a package.json. Each `||` alternative is kept as its own
+/// `VersionReq`, and a version is part of the range when any of them matches it.
+#[derive(Clone, Debug)]
+pub struct VersionRange(pub Vec<VersionReq>);
+
+impl VersionRange {
+    /// Like npm, a prerelease is only matched by an alternative with a comparator on the
+    /// same `major.minor.patch` that mentions a prerelease too, e.g. `>=2.0.0-rc.0`.
+    pub fn matches(&self, version: &Version) -> bool {
+        self.0
+            .iter()
+            .any(|requirement| requirement.matches(version))
+    }
+}
+
+impl Display for VersionRange {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let alternatives = self.0.iter().map(|r| r.to_string()).collect::<Vec<_>>();
+        write!(f, "{}", alternatives.join(" || "))
+    }
+}
+
+/// Which of the versions matching a range is resolved.
+#[derive(Clone, Copy, Default, PartialEq)]
+pub enum Preference {
+    #[default]
+    Highest,
+    /// The lowest, to check that the lower bounds of the ranges still work.
+    Lowest,
+}
 
 pub struct Versions;
 impl Versions {
-    pub fn parse_semantic_version(raw_version: &String) -> Result<Comparator, ParseError> {
-        let mut version =
-            VersionReq::parse(raw_version).map_err(ParseError::InvalidVersionNotation)?;
-        Ok(version.comparators.remove(0))
+    pub fn parse_semantic_version(raw_version: &str) -> Result<VersionRange, ParseError> {
+        raw_version
+            .split("||")
+            .map(|alternative| match alternative.trim() {
+                "" => Ok(VersionReq::STAR),
+                alternative => VersionReq::parse(&Self::to_comma_separated(alternative))
+                    .map_err(ParseError::InvalidVersionNotation),
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .map(VersionRange)
     }
 
-    pub fn parse_semantic_package_details(details: String) -> Result<PackageDetails, ParseError> {
-        let (name, version) = Self::parse_raw_package_details(details);
+    /// npm separates the comparators of a range with spaces (`>=1.2.0 <2.0.0`) and supports
+    /// hyphen ranges (`1.2.0 - 2.0.0`), while `VersionReq` expects `>=1.2.0, <2.0.0`.
+    fn to_comma_separated(alternative: &str) -> String {
+        let tokens = alternative.split_whitespace().collect::<Vec<_>>();
 
-        if version == LATEST {
-            return Ok((name, None));
+        if let [lower, "-", upper] = tokens.as_slice() {
+            return format!(">={lower}, <={upper}");
         }
 
-        let version = Self::parse_semantic_version(&version)?;
-        return Ok((name, Some(version)));
+        let mut comparators: Vec<String> = Vec::new();
+        let mut pending_op = String::new();
+
+        for token in tokens {
+            // an operator written apart from its version (`>= 1.2.0`)
+            if token.chars().all(|c| "<>=~^".contains(c)) {
+                pending_op.push_str(token);
+                continue;
+            }
+
+            let token = token.trim_end_matches(',');
+            comparators.push(format!("{pending_op}{}", Self::expand_partial(token)));
+            pending_op.clear();
+        }
+
+        comparators.join(", ")
     }
 
-    pub fn parse_raw_package_details(package: String) -> (String, String) {
-        let mut sp = package.split("@");
+    /// npm reads a bare partial version as an x-range, `1` is `1.x` and `1.2` is `1.2.x`, and
+    /// a bare full version as that exact version, while `VersionReq` would read `1.2` as
+    /// `^1.2` and `1.2.3` as `^1.2.3`.
+    fn expand_partial(token: &str) -> String {
+        let is_bare = token.starts_with(|c: char| c.is_ascii_digit());
+        let is_partial = token.split('.').count() < 3
+            && token.split('.').all(|part| part.parse::<u64>().is_ok());
+
+        match (is_bare, is_partial) {
+            (true, true) => format!("{token}.*"),
+            (true, false) if Version::parse(token).is_ok() => format!("={token}"),
+            _ => token.to_string(),
+        }
+    }
+
+    /// Parses a version range, falling back to a dist-tag when the string isn't a range.
+    pub fn parse_version_spec(raw_version: &str) -> Result<VersionSpec, ParseError> {
+        if Self::is_tarball_url(raw_version) {
+            return Ok(VersionSpec::Tarball(raw_version.to_string()));
+        }
+
+        if let Some(path) = Self::local_path(raw_version) {
+            return Ok(VersionSpec::Local(path));
+        }
+
+        match Self::parse_semantic_version(raw_version) {
+            Ok(range) => Ok(VersionSpec::Range(range)),
+            Err(_) if Self::is_dist_tag(raw_version) => {
+                Ok(VersionSpec::Tag(raw_version.to_string()))
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// A spec only matching `version`, which must be a valid version.
+    pub fn exact_spec(version: &String) -> VersionSpec {
+        let requirement = VersionReq::parse(&format!("={version}")).expect("Invalid exact version");
+        VersionSpec::Range(VersionRange(vec![requirement]))
+    }
+
+    pub fn is_tarball_url(raw_version: &str) -> bool {
+        raw_version.starts_with("http://") || raw_version.starts_with("https://")
+    }
+
+    /// The path of a `file:` spec, or of a spec that is a relative or absolute path.
+    pub fn local_path(raw_version: &str) -> Option<String> {
+        if let Some(path) = raw_version.strip_prefix("file:") {
+            return Some(path.to_string());
+        }
+
+        let is_path = ["./", "../", "/"]
+            .iter()
+            .any(|prefix| raw_version.starts_with(prefix));
+        is_path.then(|| raw_version.to_string())
+    }
+
+    /// Dist-tags can't be mistaken for versions, so they never start with a digit.
+    pub fn is_dist_tag(raw_version: &str) -> bool {
+        raw_version.starts_with(|c: char| c.is_ascii_alphabetic())
+            && raw_version
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "-_.".contains(c))
+    }
+
+    /// Checks a name against npm's rules for new packages: at most 214 characters, lowercase,
+    /// made of URL-safe characters and not starting with `.` or `_`, with an optional `@scope/`.
+    pub fn validate_package_name(name: &str) -> Result<(), ParseError> {
+        let invalid = |reason: &str| {
+            Err(ParseError::InvalidPackageName(
+                name.to_string(),
+                reason.to_string(),
+            ))
+        };
+
+        if name.is_empty() {
+            return invalid("it is empty");
+        }
+        if name.len() > MAX_PACKAGE_NAME_LENGTH {
+            return invalid("it is longer than 214 characters");
+        }
+
+        let parts = match name.strip_prefix('@') {
+            Some(scoped) => match scoped.split_once('/') {
+                Some((scope, bare_name)) => vec![scope, bare_name],
+                None => return invalid("a scoped name must look like '@scope/name'"),
+            },
+            None => vec![name],
+        };
+
+        for part in parts {
+            if part.is_empty() {
+                return invalid("a scoped name must look like '@scope/name'");
+            }
+            if part.starts_with(['.', '_']) {
+                return invalid("it can't start with '.' or '_'");
+            }
+            if part.chars().any(|c| c.is_ascii_uppercase()) {
+                return invalid("it can't contain capital letters");
+            }
+            if !part
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || "-._".contains(c))
+            {
+                return invalid("it can only contain letters, digits, '-', '.' and '_'");
+            }
+        }
+
+        if name == "node_modules" || name == "favicon.ico" {
+            return invalid("it is a reserved name");
+        }
 
-        let name = sp.next().expect("Failed to get package name").to_string();
+        Ok(())
+    }
 
-        match sp.next() {
-            Some(v) => (name, v.to_string()),
-            None => (name, String::from(LATEST)),
+    /// Parses a `name@version` argument. A tarball URL or a local path is kept whole as both
+    /// the name and the spec, the actual name is only known once the package is read.
+    pub fn parse_semantic_package_details(details: String) -> Result<PackageDetails, ParseError> {
+        if Self::is_tarball_url(&details) || Self::local_path(&details).is_some() {
+            return Ok((details.to_string(), Self::parse_version_spec(&details)?));
         }
+
+        let (name, version) = Self::parse_raw_package_details(details);
+        Ok((name, Self::parse_version_spec(&version)?))
     }
 
-    pub fn resolve_full_version(semantic_version: Option<&Comparator>) -> Option<String> {
-        let latest = String::from(LATEST);
+    /// Splits `name@version` into its parts. Scoped packages (`@scope/name`) start with an `@`
+    /// that belongs to the name, so only an `@` found after the first character is a delimiter.
+    pub fn parse_raw_package_details(package: String) -> (String, String) {
+        match package.rfind('@') {
+            Some(pos) if pos > 0 => (package[..pos].to_string(), package[pos + 1..].to_string()),
+            _ => (package, String::from(LATEST)),
+        }
+    }
 
-        let semantic_version = match semantic_version {
-            Some(semantic_version) => semantic_version,
-            None => return Some(latest),
+    pub fn resolve_full_version(version_spec: &VersionSpec) -> Option<String> {
+        let semantic_version = match version_spec {
+            VersionSpec::Range(semantic_version) => semantic_version,
+            VersionSpec::Tag(tag) => return Some(tag.to_string()),
+            VersionSpec::Tarball(_) | VersionSpec::Local(_) => return None,
         };
 
-        let (minor, patch) = match (semantic_version.minor, semantic_version.patch) {
-            (Some(minor), Some(patch)) => (minor, patch),
+        // only a range made of a single comparator can be resolved without the published versions
+        let semantic_version = match semantic_version.0.as_slice() {
+            [requirement] if requirement.comparators.len() == 1 => &requirement.comparators[0],
             _ => return None,
         };
 
-        match semantic_version.op {
-            Op::Greater | Op::GreaterEq | Op::Wildcard => Some(latest),
-            Op::Exact | Op::LessEq | Op::Tilde | Op::Caret => Some(Self::stringify_from_nums(
+        // a range allowing prereleases is matched against every version, but an exact one
+        // pins a single prerelease, e.g. a locked one
+        if !semantic_version.pre.is_empty() {
+            return match (
+                semantic_version.op,
+                semantic_version.minor,
+                semantic_version.patch,
+            ) {
+                (Op::Exact, Some(minor), Some(patch)) => Some(format!(
+                    "{}-{}",
+                    Self::stringify_from_nums(semantic_version.major, minor, patch),
+                    semantic_version.pre
+                )),
+                _ => None,
+            };
+        }
+
+        // any other range, e.g. `^1.2.3`, `~1.2` or `1.x`, matches several versions, which
+        // only the published ones can settle
+        match (
+            semantic_version.op,
+            semantic_version.minor,
+            semantic_version.patch,
+        ) {
+            (Op::Exact, Some(minor), Some(patch)) => Some(Self::stringify_from_nums(
                 semantic_version.major,
                 minor,
                 patch,
@@ -61,66 +291,248 @@ impl Versions {
         }
     }
 
+    /// Like npm, the version of the `latest` dist-tag is preferred when it matches, as it can
+    /// be older than the highest published one. Otherwise the highest matching one is taken,
+    /// or the lowest one when it is preferred.
     pub fn resolve_partial_version(
-        semantic_version: Option<&Comparator>,
-        available_versions: &HashMap<String, VersionData>,
+        semantic_version: Option<&VersionRange>,
+        package_data: &PackageData,
+        preference: Preference,
     ) -> Result<String, CommandError> {
         let semantic_version = semantic_version
             .expect("Function should not be called as the version can be resolved to 'latest'");
 
-        let mut versions = available_versions.iter().collect::<Vec<_>>();
+        let latest = package_data
+            .dist_tags
+            .get(LATEST)
+            .filter(|_| preference == Preference::Highest)
+            .filter(|latest| package_data.versions.contains_key(*latest))
+            .filter(|latest| Version::parse(latest).is_ok_and(|v| semantic_version.matches(&v)));
+        if let Some(latest) = latest {
+            return Ok(latest.to_string());
+        }
 
-        Self::sort(&mut versions);
+        // a version the registry lists but which isn't valid semver can't be matched
+        let matching = package_data
+            .versions
+            .keys()
+            .filter_map(|version| Version::parse(version).ok())
+            .filter(|version| semantic_version.matches(version));
+        match preference {
+            Preference::Highest => matching.max(),
+            Preference::Lowest => matching.min(),
+        }
+        .map(|version| version.to_string())
+        .ok_or(CommandError::InvalidVersion(semantic_version.to_string()))
+    }
 
-        if semantic_version.op == Op::Less {
-            if let (Some(minor), Some(patch)) = (semantic_version.minor, semantic_version.patch) {
-                let version_pos = versions
-                    .iter()
-                    .position(|(v, _)| {
-                        v == &&Self::stringify_from_nums(semantic_version.major, minor, patch)
-                    })
-                    .ok_or(CommandError::InvalidVersion)?;
+    /// Sorts versions in ascending order, dropping the ones that aren't valid semver.
+    pub fn sort(versions: &mut Vec<(&String, &VersionData)>) {
+        versions.retain(|(version, _)| Version::parse(version).is_ok());
+        versions.sort_by_cached_key(|(version, _)| {
+            Version::parse(version).expect("Invalid versions are dropped")
+        });
+    }
 
-                return Ok(versions
-                    .get(version_pos - 1)
-                    .expect("No previous version found")
-                    .0
-                    .to_string());
-            }
-        }
+    pub fn stringify(name: &String, version: &String) -> String {
+        format!("{}@{}", name, version)
+    }
 
-        for (version, _) in versions.iter().rev() {
-            let version = Version::from_str(version.as_str()).unwrap_or(EMPTY_VERSION);
+    pub fn stringify_from_nums(major: u64, minor: u64, patch: u64) -> String {
+        format!("{}.{}.{}", major, minor, patch)
+    }
+}
 
-            if semantic_version.matches(&version) {
-                return Ok(version.to_string());
-            }
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
 
-        Err(CommandError::InvalidVersion)
+    fn details(package: &str) -> (String, String) {
+        Versions::parse_raw_package_details(package.to_string())
     }
 
-    fn sort(versions: &mut Vec<(&String, &VersionData)>) {
-        versions.sort_by(|a, b| {
-            let a = Version::parse(a.0).expect("Failed to parse version");
-            let b = Version::parse(b.0).expect("Failed to parse version");
+    fn package_data(versions: &[&str], latest: &str) -> PackageData {
+        let versions = versions
+            .iter()
+            .map(|version| {
+                (
+                    version.to_string(),
+                    json!({ "name": "pkg", "version": version }),
+                )
+            })
+            .collect::<serde_json::Map<_, _>>();
+        serde_json::from_value(json!({ "versions": versions, "dist-tags": { "latest": latest } }))
+            .unwrap()
+    }
 
-            a.cmp(&b)
-        });
+    fn resolve(range: &str, package_data: &PackageData, preference: Preference) -> String {
+        let range = Versions::parse_semantic_version(range).unwrap();
+        Versions::resolve_partial_version(Some(&range), package_data, preference).unwrap()
+    }
+
+    #[test]
+    fn splits_unscoped_packages() {
+        assert_eq!(
+            details("express@4.17.1"),
+            ("express".into(), "4.17.1".into())
+        );
+        assert_eq!(details("express"), ("express".into(), LATEST.into()));
     }
 
-    pub fn is_latest(version_string: Option<&String>) -> bool {
-        match version_string {
-            Some(version) => version == LATEST,
-            None => false,
+    #[test]
+    fn keeps_the_scope_in_the_name() {
+        assert_eq!(
+            details("@types/node"),
+            ("@types/node".into(), LATEST.into())
+        );
+        assert_eq!(
+            details("@types/node@^18"),
+            ("@types/node".into(), "^18".into())
+        );
+    }
+
+    #[test]
+    fn takes_the_highest_match_across_alternatives() {
+        let package_data = package_data(&["1.0.0", "1.5.0", "2.0.0", "2.3.1", "3.0.0"], "3.0.0");
+        assert_eq!(
+            resolve("^1.0 || ^2.0", &package_data, Preference::Highest),
+            "2.3.1"
+        );
+        assert_eq!(
+            resolve("^2.0 || ^1.0", &package_data, Preference::Highest),
+            "2.3.1"
+        );
+        assert_eq!(
+            resolve("<1.2.0 || >2.0.0 <3", &package_data, Preference::Highest),
+            "2.3.1"
+        );
+    }
+
+    #[test]
+    fn reads_partial_versions_as_x_ranges() {
+        let package_data = package_data(&["1.0.0", "1.2.0", "1.2.5", "1.3.0", "2.0.0"], "2.0.0");
+        for (range, resolved) in [
+            ("1", "1.3.0"),
+            ("1.x", "1.3.0"),
+            ("1.2", "1.2.5"),
+            ("1.2.x", "1.2.5"),
+        ] {
+            assert_eq!(
+                Versions::resolve_full_version(&Versions::parse_version_spec(range).unwrap()),
+                None
+            );
+            assert_eq!(
+                resolve(range, &package_data, Preference::Highest),
+                resolved,
+                "{range}"
+            );
         }
     }
 
-    pub fn stringify(name: &String, version: &String) -> String {
-        format!("{}@{}", name, version)
+    #[test]
+    fn only_matches_prereleases_a_range_mentions() {
+        let package_data = package_data(&["1.0.0", "1.1.0", "2.0.0-beta.1"], "1.0.0");
+        assert_eq!(
+            resolve(">=1.1.0", &package_data, Preference::Highest),
+            "1.1.0"
+        );
+        assert_eq!(resolve("*", &package_data, Preference::Highest), "1.0.0");
+        assert_eq!(
+            resolve(">=2.0.0-beta.0", &package_data, Preference::Highest),
+            "2.0.0-beta.1"
+        );
     }
 
-    pub fn stringify_from_nums(major: u64, minor: u64, patch: u64) -> String {
-        format!("{}.{}.{}", major, minor, patch)
+    #[test]
+    fn accepts_valid_package_names() {
+        for name in [
+            "express",
+            "lodash.merge",
+            "@types/node",
+            "@babel/core",
+            "a-b_c.d1",
+        ] {
+            assert!(Versions::validate_package_name(name).is_ok(), "{name}");
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_package_names() {
+        let too_long = "a".repeat(MAX_PACKAGE_NAME_LENGTH + 1);
+        for name in [
+            "",
+            "Foo",
+            "foo bar",
+            "../etc/passwd",
+            ".hidden",
+            "_private",
+            "@scope",
+            "@scope/",
+            "@/name",
+            "@scope/na/me",
+            "node_modules",
+            &too_long,
+        ] {
+            assert!(
+                matches!(
+                    Versions::validate_package_name(name),
+                    Err(ParseError::InvalidPackageName(..))
+                ),
+                "{name}"
+            );
+        }
+    }
+
+    #[test]
+    fn prefers_the_latest_tag_over_newer_versions() {
+        let package_data = package_data(&["1.0.0", "1.1.0", "2.0.0-beta.1"], "1.0.0");
+        assert_eq!(resolve("*", &package_data, Preference::Highest), "1.0.0");
+        assert_eq!(
+            resolve("^1.0.0", &package_data, Preference::Highest),
+            "1.0.0"
+        );
+        assert_eq!(
+            resolve(">=1.0.1", &package_data, Preference::Highest),
+            "1.1.0"
+        );
+    }
+
+    #[test]
+    fn only_resolves_exact_versions_without_the_published_ones() {
+        let full_version = |spec: &str| {
+            Versions::resolve_full_version(&Versions::parse_version_spec(spec).unwrap())
+        };
+        assert_eq!(full_version("1.2.3"), Some(String::from("1.2.3")));
+        assert_eq!(full_version("=1.2.3"), Some(String::from("1.2.3")));
+        assert_eq!(
+            full_version("1.2.3-beta.1"),
+            Some(String::from("1.2.3-beta.1"))
+        );
+        assert_eq!(full_version(LATEST), Some(String::from(LATEST)));
+        for range in ["^1.2.3", "~1.2.3", "<=1.2.3", ">=1.2.3", "^1.2.3-beta.1"] {
+            assert_eq!(full_version(range), None, "{range}");
+        }
+    }
+
+    #[test]
+    fn resolves_the_lowest_or_the_highest_match() {
+        let package_data = package_data(&["1.0.0", "1.2.0", "1.4.0", "2.0.0"], "1.4.0");
+        for (range, lowest, highest) in [
+            ("^1.1.0", "1.2.0", "1.4.0"),
+            (">=1.0.0 <2.0.0", "1.0.0", "1.4.0"),
+            ("^1.0.0 || ^2.0.0", "1.0.0", "1.4.0"),
+        ] {
+            assert_eq!(
+                resolve(range, &package_data, Preference::Lowest),
+                lowest,
+                "{range}"
+            );
+            assert_eq!(
+                resolve(range, &package_data, Preference::Highest),
+                highest,
+                "{range}"
+            );
+        }
     }
 }
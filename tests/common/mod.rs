@@ -0,0 +1,426 @@
+//! A registry serving packages from memory, and projects installing from it with their own
+//! cache, data and home directories, so that tests never touch the real ones.
+#![allow(dead_code)]
+
+use base64::Engine;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde_json::{json, Map, Value};
+use sha1::Sha1;
+use sha2::{Digest, Sha512};
+use std::collections::HashMap;
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// A version of a package the registry publishes.
+pub struct Package {
+    name: String,
+    version: String,
+    dependencies: Map<String, Value>,
+    files: Vec<(String, Vec<u8>)>,
+    has_tarball: bool,
+    serves_tarball: bool,
+    is_latest: bool,
+}
+
+impl Package {
+    pub fn new(name: &str, version: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            version: version.to_string(),
+            dependencies: Map::new(),
+            files: vec![(String::from("index.js"), b"module.exports = {};\n".to_vec())],
+            has_tarball: true,
+            serves_tarball: true,
+            is_latest: false,
+        }
+    }
+
+    pub fn dependency(mut self, name: &str, range: &str) -> Self {
+        self.dependencies.insert(name.to_string(), json!(range));
+        self
+    }
+
+    pub fn file(mut self, path: &str, contents: &[u8]) -> Self {
+        self.files.push((path.to_string(), contents.to_vec()));
+        self
+    }
+
+    /// Published without a `dist.tarball`, like some deprecated versions are.
+    pub fn without_tarball(mut self) -> Self {
+        self.has_tarball = false;
+        self
+    }
+
+    /// Published with a `dist.tarball` the registry answers 404 for.
+    pub fn with_missing_tarball(mut self) -> Self {
+        self.serves_tarball = false;
+        self
+    }
+
+    /// Makes this version the `latest` dist-tag, which is otherwise the highest stable one.
+    pub fn latest(mut self) -> Self {
+        self.is_latest = true;
+        self
+    }
+
+    pub fn stringified(&self) -> String {
+        format!("{}@{}", self.name, self.version)
+    }
+
+    fn manifest(&self) -> Value {
+        json!({
+            "name": self.name,
+            "version": self.version,
+            "dependencies": self.dependencies,
+        })
+    }
+
+    fn tarball(&self) -> Vec<u8> {
+        let mut archive = tar::Builder::new(GzEncoder::new(Vec::new(), Compression::default()));
+        let manifest = serde_json::to_vec_pretty(&self.manifest()).unwrap();
+        let files = [(String::from("package.json"), manifest)]
+            .into_iter()
+            .chain(self.files.iter().cloned());
+        for (path, contents) in files {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(contents.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            archive
+                .append_data(&mut header, format!("package/{path}"), contents.as_slice())
+                .unwrap();
+        }
+        archive.into_inner().unwrap().finish().unwrap()
+    }
+
+    fn tarball_route(&self) -> String {
+        format!("-/{}-{}.tgz", self.name.replace('/', "-"), self.version)
+    }
+}
+
+/// A request the registry received, with the status it answered.
+#[derive(Clone, Debug)]
+pub struct Request {
+    pub path: String,
+    pub headers: HashMap<String, String>,
+    pub status: u16,
+}
+
+#[derive(Default)]
+struct Routes {
+    documents: HashMap<String, String>,
+    tarballs: HashMap<String, Vec<u8>>,
+}
+
+pub struct Registry {
+    pub url: String,
+    routes: Arc<Routes>,
+    requests: Arc<Mutex<Vec<Request>>>,
+}
+
+impl Registry {
+    pub fn start(packages: Vec<Package>) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let routes = Arc::new(Self::routes(&url, packages));
+        let requests = Arc::new(Mutex::new(Vec::new()));
+
+        let (served, log) = (routes.clone(), requests.clone());
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let (served, log) = (served.clone(), log.clone());
+                thread::spawn(move || Self::serve(stream, &served, &log));
+            }
+        });
+
+        Self {
+            url,
+            routes,
+            requests,
+        }
+    }
+
+    fn routes(url: &str, packages: Vec<Package>) -> Routes {
+        let mut routes = Routes::default();
+        let mut packuments: HashMap<String, (Map<String, Value>, Option<String>)> = HashMap::new();
+
+        for package in &packages {
+            let mut document = package.manifest();
+            let tarball = package.tarball();
+            let integrity =
+                base64::engine::general_purpose::STANDARD.encode(Sha512::digest(&tarball));
+            document["dist"] = json!({
+                "tarball": match package.has_tarball {
+                    true => format!("{url}/{}", package.tarball_route()),
+                    false => String::new(),
+                },
+                "shasum": format!("{:x}", Sha1::digest(&tarball)),
+                "integrity": format!("sha512-{integrity}"),
+            });
+            if package.serves_tarball {
+                routes.tarballs.insert(package.tarball_route(), tarball);
+            }
+            routes.documents.insert(
+                format!("{}/{}", package.name, package.version),
+                document.to_string(),
+            );
+
+            let (versions, latest) = packuments.entry(package.name.to_string()).or_default();
+            versions.insert(package.version.to_string(), document);
+            if package.is_latest {
+                *latest = Some(package.version.to_string());
+            }
+        }
+
+        for (name, (versions, latest)) in packuments {
+            let latest = latest.or_else(|| {
+                versions
+                    .keys()
+                    .filter_map(|version| semver::Version::parse(version).ok())
+                    .filter(|version| version.pre.is_empty())
+                    .max()
+                    .map(|version| version.to_string())
+            });
+            if let Some(document) = latest.as_ref().map(|latest| &versions[latest]) {
+                routes
+                    .documents
+                    .insert(format!("{name}/latest"), document.to_string());
+            }
+            let packument = json!({
+                "name": name,
+                "dist-tags": { "latest": latest },
+                "versions": versions,
+                "modified": "2024-01-01T00:00:00.000Z",
+            });
+            routes.documents.insert(name, packument.to_string());
+        }
+
+        routes
+    }
+
+    fn serve(mut stream: TcpStream, routes: &Routes, log: &Mutex<Vec<Request>>) {
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut request_line = String::new();
+        if reader.read_line(&mut request_line).is_err() {
+            return;
+        }
+        let path = request_line.split_whitespace().nth(1).unwrap_or("/");
+        let path = path
+            .split('?')
+            .next()
+            .unwrap_or_default()
+            .trim_start_matches('/');
+        let path = path.replace("%2f", "/").replace("%2F", "/");
+
+        let mut headers = HashMap::new();
+        loop {
+            let mut line = String::new();
+            if reader.read_line(&mut line).is_err() || line.trim().is_empty() {
+                break;
+            }
+            if let Some((name, value)) = line.split_once(':') {
+                headers.insert(name.trim().to_lowercase(), value.trim().to_string());
+            }
+        }
+
+        let (status, extra_headers, body) = Self::answer(routes, &path, &headers);
+        log.lock().unwrap().push(Request {
+            path: path.to_string(),
+            headers,
+            status,
+        });
+
+        let mut response = format!(
+            "HTTP/1.1 {status} {}\r\nContent-Length: {}\r\nConnection: close\r\n",
+            if status < 400 { "OK" } else { "Error" },
+            body.len()
+        );
+        for (name, value) in extra_headers {
+            response.push_str(&format!("{name}: {value}\r\n"));
+        }
+        response.push_str("\r\n");
+        let _ = stream.write_all(response.as_bytes());
+        let _ = stream.write_all(&body);
+    }
+
+    fn answer(
+        routes: &Routes,
+        path: &str,
+        headers: &HashMap<String, String>,
+    ) -> (u16, Vec<(String, String)>, Vec<u8>) {
+        if let Some(tarball) = routes.tarballs.get(path) {
+            let start = headers
+                .get("range")
+                .and_then(|range| range.strip_prefix("bytes="))
+                .and_then(|range| range.trim_end_matches('-').parse::<usize>().ok());
+            return match start {
+                Some(start) if start >= tarball.len() => (416, Vec::new(), Vec::new()),
+                Some(start) => {
+                    let range = format!("bytes {start}-{}/{}", tarball.len() - 1, tarball.len());
+                    (
+                        206,
+                        vec![("Content-Range".into(), range)],
+                        tarball[start..].to_vec(),
+                    )
+                }
+                None => (200, Vec::new(), tarball.clone()),
+            };
+        }
+
+        let Some(document) = routes.documents.get(path) else {
+            return (404, Vec::new(), br#"{"error":"Not found"}"#.to_vec());
+        };
+        let etag = format!("\"{:x}\"", Sha1::digest(document.as_bytes()));
+        let etag_header = vec![(String::from("ETag"), etag.to_string())];
+        match headers.get("if-none-match") == Some(&etag) {
+            true => (304, etag_header, Vec::new()),
+            false => (200, etag_header, document.as_bytes().to_vec()),
+        }
+    }
+
+    pub fn requests(&self) -> Vec<Request> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    pub fn clear_requests(&self) {
+        self.requests.lock().unwrap().clear();
+    }
+
+    /// The tarball of a package, as the registry serves it.
+    pub fn tarball(&self, name: &str, version: &str) -> Vec<u8> {
+        let route = format!("-/{}-{version}.tgz", name.replace('/', "-"));
+        self.routes.tarballs[&route].clone()
+    }
+}
+
+static PROJECTS: AtomicUsize = AtomicUsize::new(0);
+
+/// A project in a temporary directory, removed once the test is done.
+pub struct Project {
+    pub dir: PathBuf,
+    root: PathBuf,
+    registry: String,
+}
+
+impl Project {
+    pub fn new(registry: &Registry) -> Self {
+        let id = PROJECTS.fetch_add(1, Ordering::SeqCst);
+        let root = std::env::temp_dir().join(format!("pie-test-{}-{id}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let dir = root.join("project");
+        fs::create_dir_all(&dir).unwrap();
+        for home in ["home", "data"] {
+            fs::create_dir_all(root.join(home)).unwrap();
+        }
+
+        let project = Self {
+            dir,
+            root,
+            registry: registry.url.to_string(),
+        };
+        project.write(
+            "package.json",
+            r#"{ "name": "project", "version": "1.0.0" }"#,
+        );
+        project
+    }
+
+    pub fn cache_dir(&self) -> PathBuf {
+        self.root.join("cache")
+    }
+
+    /// A path next to the project, outside of it.
+    pub fn outside(&self, path: &str) -> PathBuf {
+        self.root.join(path)
+    }
+
+    pub fn path(&self, path: &str) -> PathBuf {
+        self.dir.join(path)
+    }
+
+    pub fn write(&self, path: &str, contents: &str) {
+        let path = self.path(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+
+    pub fn read(&self, path: &str) -> String {
+        fs::read_to_string(self.path(path)).unwrap()
+    }
+
+    pub fn read_json(&self, path: &str) -> Value {
+        serde_json::from_str(&self.read(path)).unwrap()
+    }
+
+    pub fn package_json(&self) -> Value {
+        self.read_json("package.json")
+    }
+
+    /// `pie` run from the project, isolated from the settings of the machine.
+    pub fn command(&self, args: &[&str]) -> Command {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_pie"));
+        for (name, _) in std::env::vars().filter(|(name, _)| name.starts_with("PIE_")) {
+            command.env_remove(name);
+        }
+        command
+            .args(args)
+            .current_dir(&self.dir)
+            .env("PIE_REGISTRY", &self.registry)
+            .env("PIE_CACHE_DIR", self.cache_dir())
+            .env("HOME", self.root.join("home"))
+            .env("XDG_CACHE_HOME", self.root.join("home/.cache"))
+            .env("XDG_DATA_HOME", self.root.join("data"))
+            .env("RUST_BACKTRACE", "0");
+        command
+    }
+
+    pub fn pie(&self, args: &[&str]) -> Output {
+        self.command(args).output().unwrap()
+    }
+
+    /// Runs `pie`, failing the test unless it succeeds, and returns what it printed.
+    pub fn pie_ok(&self, args: &[&str]) -> String {
+        succeeded(self.pie(args))
+    }
+}
+
+impl Drop for Project {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.root);
+    }
+}
+
+pub fn succeeded(output: Output) -> String {
+    let printed = printed(&output);
+    assert!(output.status.success(), "pie failed:\n{printed}");
+    printed
+}
+
+pub fn failed(output: Output) -> String {
+    let printed = printed(&output);
+    assert!(!output.status.success(), "pie succeeded:\n{printed}");
+    printed
+}
+
+fn printed(output: &Output) -> String {
+    format!(
+        "{}{}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    )
+}
+
+/// Whether a package of `node_modules` is there, following its link into the cache.
+pub fn is_installed(dir: &Path, name: &str) -> bool {
+    dir.join("node_modules")
+        .join(name)
+        .join("package.json")
+        .exists()
+}
@@ -0,0 +1,623 @@
+mod common;
+
+use common::{is_installed, Package, Project, Registry};
+
+#[test]
+fn relinks_a_package_installed_at_another_version() {
+    let registry = Registry::start(vec![
+        Package::new("pkg", "1.0.0"),
+        Package::new("pkg", "2.0.0"),
+    ]);
+    let project = Project::new(&registry);
+    project.pie_ok(&["install", "pkg@1.0.0"]);
+
+    project.pie_ok(&["install", "pkg@2"]);
+
+    assert_eq!(
+        project.read_json("node_modules/pkg/package.json")["version"],
+        "2.0.0"
+    );
+    assert_eq!(
+        project.read_json("pie-lock.json")["dependencies"]["pkg"],
+        "2.0.0"
+    );
+}
+
+#[test]
+fn links_packages_sharing_a_scope_into_one_directory() {
+    let registry = Registry::start(vec![
+        Package::new("@scope/first", "1.0.0"),
+        Package::new("@scope/second", "1.0.0"),
+    ]);
+    let project = Project::new(&registry);
+
+    project.pie_ok(&["install", "@scope/first", "@scope/second"]);
+
+    assert!(is_installed(&project.dir, "@scope/first"));
+    assert!(is_installed(&project.dir, "@scope/second"));
+    assert!(project.path("node_modules/@scope").is_dir());
+}
+
+#[test]
+fn extracts_every_package_of_a_large_install() {
+    let dependencies = (0..60).map(|i| format!("dep-{i}")).collect::<Vec<_>>();
+    let mut root = Package::new("root", "1.0.0");
+    let mut packages = Vec::new();
+    for name in &dependencies {
+        root = root.dependency(name, "^1.0.0");
+        let files = (0..20).map(|i| (format!("lib/file-{i}.js"), vec![b'x'; 4096]));
+        packages.push(
+            files.fold(Package::new(name, "1.0.0"), |package, (path, contents)| {
+                package.file(&path, &contents)
+            }),
+        );
+    }
+    packages.push(root);
+    let registry = Registry::start(packages);
+    let project = Project::new(&registry);
+
+    project.pie_ok(&["install", "root"]);
+
+    for name in &dependencies {
+        assert!(
+            project
+                .cache_dir()
+                .join(format!("{name}@1.0.0/package/lib/file-19.js"))
+                .exists(),
+            "{name} wasn't fully extracted"
+        );
+    }
+}
+
+#[test]
+fn terminates_on_a_dependency_cycle() {
+    let registry = Registry::start(vec![
+        Package::new("first", "1.0.0").dependency("second", "^1.0.0"),
+        Package::new("second", "1.0.0").dependency("third", "^1.0.0"),
+        Package::new("third", "1.0.0").dependency("first", "^1.0.0"),
+    ]);
+    let project = Project::new(&registry);
+
+    // once from the registry, then from the cache
+    for _ in 0..2 {
+        let output = project
+            .command(&["install", "first"])
+            .env("PIE_INSTALL_TIMEOUT", "30")
+            .output()
+            .unwrap();
+        common::succeeded(output);
+        assert!(is_installed(&project.dir, "first"));
+    }
+}
+
+#[test]
+fn reads_back_whether_a_cached_version_was_the_latest() {
+    let registry = Registry::start(vec![
+        Package::new("pkg", "1.0.0"),
+        Package::new("pkg", "2.0.0"),
+    ]);
+    let project = Project::new(&registry);
+    project.pie_ok(&["install", "pkg@1.0.0"]);
+    project.pie_ok(&["install", "pkg"]);
+
+    let lock_path = |version: &str| {
+        project
+            .cache_dir()
+            .join(format!("pkg@{version}/package/pie-lock.json"))
+    };
+    let read_lock = |version: &str| -> serde_json::Value {
+        serde_json::from_str(&std::fs::read_to_string(lock_path(version)).unwrap()).unwrap()
+    };
+    assert_eq!(read_lock("1.0.0")["isLatest"], false);
+    assert_eq!(read_lock("2.0.0")["isLatest"], true);
+
+    // the lock is read whatever its layout, e.g. once reformatted with its keys reordered
+    let lock = read_lock("2.0.0").as_object().unwrap().clone();
+    let reordered = lock.into_iter().rev().collect::<serde_json::Map<_, _>>();
+    std::fs::write(
+        lock_path("2.0.0"),
+        serde_json::to_string(&reordered).unwrap(),
+    )
+    .unwrap();
+
+    registry.clear_requests();
+    project.pie_ok(&["install", "pkg"]);
+    assert!(registry.requests().is_empty(), "{:?}", registry.requests());
+    assert_eq!(project.package_json()["dependencies"]["pkg"], "^2.0.0");
+}
+
+#[test]
+fn records_whether_a_dependency_resolved_to_the_latest_version() {
+    let registry = Registry::start(vec![
+        Package::new("top", "1.0.0")
+            .dependency("current", "^1.0.0")
+            .dependency("old", "^1.0.0"),
+        Package::new("current", "1.0.0"),
+        Package::new("old", "1.0.0"),
+        Package::new("old", "2.0.0"),
+    ]);
+    let project = Project::new(&registry);
+    project.pie_ok(&["install", "top"]);
+
+    let read_lock = |package: &str| -> serde_json::Value {
+        let path = project
+            .cache_dir()
+            .join(format!("{package}/package/pie-lock.json"));
+        serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap()
+    };
+    assert_eq!(read_lock("current@1.0.0")["isLatest"], true);
+    assert_eq!(read_lock("old@1.0.0")["isLatest"], false);
+}
+
+#[test]
+fn installs_the_latest_stable_version_over_a_newer_prerelease() {
+    let registry = Registry::start(vec![
+        Package::new("pkg", "1.0.0"),
+        Package::new("pkg", "1.1.0"),
+        Package::new("pkg", "2.0.0-beta.1"),
+    ]);
+    let project = Project::new(&registry);
+
+    project.pie_ok(&["install", "pkg"]);
+
+    assert_eq!(project.package_json()["dependencies"]["pkg"], "^1.1.0");
+}
+
+#[test]
+fn skips_cache_entries_with_an_invalid_version() {
+    let registry = Registry::start(vec![Package::new("pkg", "1.0.0")]);
+    let project = Project::new(&registry);
+    project.pie_ok(&["install", "pkg@1.0.0"]);
+
+    for junk in ["pkg@not-a-version", "pkg@", "pkg@1.0"] {
+        let entry = project.cache_dir().join(junk).join("package");
+        std::fs::create_dir_all(&entry).unwrap();
+        std::fs::write(entry.join("pie-lock.json"), "{}").unwrap();
+    }
+
+    project.pie_ok(&["install", "--offline", "pkg@^1.0.0"]);
+    assert!(is_installed(&project.dir, "pkg"));
+}
+
+#[test]
+fn fetches_a_package_shared_by_two_dependents_once() {
+    let registry = Registry::start(vec![
+        Package::new("top", "1.0.0")
+            .dependency("left", "^1.0.0")
+            .dependency("right", "^1.0.0"),
+        Package::new("left", "1.0.0").dependency("shared", "^1.0.0"),
+        Package::new("right", "1.0.0").dependency("shared", "^1.0.0"),
+        Package::new("shared", "1.0.0"),
+    ]);
+    let project = Project::new(&registry);
+
+    project.pie_ok(&["install", "top"]);
+
+    let requests = registry.requests();
+    let count = |path: &str| requests.iter().filter(|r| r.path == path).count();
+    assert_eq!(count("shared"), 1, "{requests:?}");
+    assert_eq!(count("-/shared-1.0.0.tgz"), 1, "{requests:?}");
+}
+
+#[test]
+fn caches_packages_in_the_configured_directory() {
+    let registry = Registry::start(vec![Package::new("pkg", "1.0.0")]);
+    let project = Project::new(&registry);
+    let cache_dir = project.outside("elsewhere/nested/cache");
+
+    let output = project
+        .command(&["install", "pkg"])
+        .env("PIE_CACHE_DIR", &cache_dir)
+        .output()
+        .unwrap();
+    common::succeeded(output);
+
+    assert!(cache_dir.join("pkg@1.0.0/package/package.json").exists());
+    assert!(!project.cache_dir().join("pkg@1.0.0").exists());
+    assert!(is_installed(&project.dir, "pkg"));
+}
+
+#[test]
+fn creates_a_missing_cache_directory() {
+    let registry = Registry::start(vec![Package::new("pkg", "1.0.0")]);
+    let project = Project::new(&registry);
+
+    // the default location, under a cache home that doesn't exist yet
+    let install = || {
+        let output = project
+            .command(&["install", "pkg"])
+            .env_remove("PIE_CACHE_DIR")
+            .output()
+            .unwrap();
+        common::succeeded(output);
+    };
+    let cache_dir = project.outside("home/.cache/pie");
+    assert!(!cache_dir.exists());
+    install();
+    assert!(cache_dir.join("pkg@1.0.0").exists());
+
+    std::fs::remove_dir_all(&cache_dir).unwrap();
+    install();
+    assert!(cache_dir.join("pkg@1.0.0").exists());
+    assert!(is_installed(&project.dir, "pkg"));
+}
+
+#[test]
+fn downloads_again_a_dependency_missing_from_the_cache() {
+    let registry = Registry::start(vec![
+        Package::new("top", "1.0.0").dependency("dep", "^1.0.0"),
+        Package::new("dep", "1.0.0"),
+    ]);
+    let project = Project::new(&registry);
+    project.pie_ok(&["install", "top"]);
+
+    std::fs::remove_dir_all(project.cache_dir().join("dep@1.0.0")).unwrap();
+    std::fs::remove_dir_all(project.path("node_modules")).unwrap();
+    registry.clear_requests();
+    project.pie_ok(&["install", "top"]);
+
+    assert!(registry
+        .requests()
+        .iter()
+        .any(|request| request.path == "-/dep-1.0.0.tgz"));
+    assert!(is_installed(&project.dir, "top"));
+    assert!(is_installed(&project.dir, "dep"));
+}
+
+#[test]
+fn resolves_a_caret_range_to_its_highest_match_over_a_cached_lower_bound() {
+    let registry = Registry::start(vec![
+        Package::new("pkg", "1.0.0"),
+        Package::new("pkg", "1.1.0"),
+    ]);
+    let project = Project::new(&registry);
+    project.pie_ok(&["install", "pkg@1.0.0"]);
+    assert_eq!(project.package_json()["dependencies"]["pkg"], "^1.0.0");
+
+    project.pie_ok(&["install", "pkg@^1.0.0"]);
+
+    assert_eq!(project.package_json()["dependencies"]["pkg"], "^1.1.0");
+    assert_eq!(
+        project.read_json("node_modules/pkg/package.json")["version"],
+        "1.1.0"
+    );
+    assert_eq!(
+        project.read_json("pie-lock.json")["dependencies"]["pkg"],
+        "1.1.0"
+    );
+}
+
+#[test]
+fn reports_packages_and_versions_the_registry_doesnt_know() {
+    let registry = Registry::start(vec![Package::new("pkg", "1.0.0")]);
+    let project = Project::new(&registry);
+
+    let printed = common::failed(project.pie(&["install", "missing"]));
+    assert!(
+        printed.contains("'missing' was not found in the registry"),
+        "{printed}"
+    );
+
+    let printed = common::failed(project.pie(&["install", "pkg@2.0.0"]));
+    assert!(
+        printed.contains("'pkg@2.0.0' was not found in the registry"),
+        "{printed}"
+    );
+    assert!(registry
+        .requests()
+        .iter()
+        .any(|request| request.status == 404));
+}
+
+#[test]
+fn reports_a_version_published_without_a_tarball() {
+    let registry = Registry::start(vec![Package::new("pkg", "1.0.0").without_tarball()]);
+    let project = Project::new(&registry);
+
+    let printed = common::failed(project.pie(&["install", "pkg"]));
+
+    assert!(
+        printed.contains("the registry has no tarball for 'pkg@1.0.0'"),
+        "{printed}"
+    );
+    assert!(!registry
+        .requests()
+        .iter()
+        .any(|request| request.path.ends_with(".tgz")));
+}
+
+#[test]
+fn reports_a_tarball_the_registry_doesnt_serve() {
+    let registry = Registry::start(vec![Package::new("pkg", "1.0.0").with_missing_tarball()]);
+    let project = Project::new(&registry);
+
+    let printed = common::failed(project.pie(&["install", "pkg"]));
+
+    assert!(printed.contains("404"), "{printed}");
+    assert!(!is_installed(&project.dir, "pkg"));
+}
+
+#[test]
+fn restarts_a_partial_download_longer_than_the_tarball() {
+    let registry = Registry::start(vec![Package::new("pkg", "1.0.0")]);
+    let project = Project::new(&registry);
+    // left by an install that crashed a while ago, of a tarball since republished
+    let partial = project.cache_dir().join(".tmp-1-pkg@1.0.0.tgz");
+    std::fs::create_dir_all(project.cache_dir()).unwrap();
+    std::fs::write(&partial, vec![0; 100_000]).unwrap();
+    std::fs::File::options()
+        .write(true)
+        .open(&partial)
+        .unwrap()
+        .set_modified(std::time::SystemTime::now() - std::time::Duration::from_secs(600))
+        .unwrap();
+
+    project.pie_ok(&["install", "pkg"]);
+
+    let statuses = registry
+        .requests()
+        .into_iter()
+        .filter(|request| request.path.ends_with(".tgz"))
+        .map(|request| request.status)
+        .collect::<Vec<_>>();
+    assert_eq!(statuses, [416, 200]);
+    assert!(is_installed(&project.dir, "pkg"));
+    assert!(!partial.exists());
+}
+
+#[test]
+fn takes_the_registry_from_the_flag_then_the_environment_then_the_pierc() {
+    let [flag, environment, pierc] =
+        [(); 3].map(|_| Registry::start(vec![Package::new("pkg", "1.0.0")]));
+    let project = Project::new(&environment);
+    project.write(".pierc", &format!("registry = \"{}\"\n", pierc.url));
+
+    let installs_from = |command: &mut std::process::Command, registry: &Registry| {
+        std::fs::remove_dir_all(project.cache_dir()).ok();
+        common::succeeded(command.output().unwrap());
+        for other in [&flag, &environment, &pierc] {
+            let used = !other.requests().is_empty();
+            assert_eq!(used, std::ptr::eq(other, registry), "{}", other.url);
+            other.clear_requests();
+        }
+    };
+
+    installs_from(
+        &mut project.command(&["install", "pkg", "--registry", &flag.url]),
+        &flag,
+    );
+    installs_from(&mut project.command(&["install", "pkg"]), &environment);
+    installs_from(
+        project
+            .command(&["install", "pkg"])
+            .env_remove("PIE_REGISTRY"),
+        &pierc,
+    );
+}
+
+#[test]
+fn saves_exact_versions_with_save_exact() {
+    let registry = Registry::start(vec![
+        Package::new("caret", "1.0.0"),
+        Package::new("exact", "1.0.0"),
+        Package::new("short", "2.1.0"),
+    ]);
+    let project = Project::new(&registry);
+
+    project.pie_ok(&["install", "caret"]);
+    project.pie_ok(&["install", "--save-exact", "exact"]);
+    project.pie_ok(&["install", "short", "-E", "-D"]);
+
+    let package_json = project.package_json();
+    assert_eq!(package_json["dependencies"]["caret"], "^1.0.0");
+    assert_eq!(package_json["dependencies"]["exact"], "1.0.0");
+    assert_eq!(package_json["devDependencies"]["short"], "2.1.0");
+}
+
+#[test]
+fn revalidates_cached_registry_documents() {
+    let registry = Registry::start(vec![
+        Package::new("pkg", "1.0.0"),
+        Package::new("pkg", "1.1.0"),
+    ]);
+    let project = Project::new(&registry);
+    project.pie_ok(&["install", "pkg@^1.0.0"]);
+
+    std::fs::remove_dir_all(project.cache_dir().join("pkg@1.1.0")).unwrap();
+    registry.clear_requests();
+    project.pie_ok(&["install", "pkg@^1.0.0"]);
+
+    let requests = registry.requests();
+    let document = requests
+        .iter()
+        .find(|request| request.path == "pkg")
+        .unwrap();
+    assert!(
+        document.headers.contains_key("if-none-match"),
+        "{document:?}"
+    );
+    assert_eq!(document.status, 304);
+    assert!(is_installed(&project.dir, "pkg"));
+    assert_eq!(project.package_json()["dependencies"]["pkg"], "^1.1.0");
+}
+
+#[test]
+fn installs_into_the_prefix_directory() {
+    let registry = Registry::start(vec![Package::new("pkg", "1.0.0")]);
+    let project = Project::new(&registry);
+    let prefix = project.outside("prefixed/app");
+
+    project.pie_ok(&["install", "pkg", "--prefix", prefix.to_str().unwrap()]);
+
+    assert!(is_installed(&prefix, "pkg"));
+    assert!(prefix.join("pie-lock.json").exists());
+    let package_json = std::fs::read_to_string(prefix.join("package.json")).unwrap();
+    assert!(
+        package_json.contains("\"pkg\": \"^1.0.0\""),
+        "{package_json}"
+    );
+    assert!(!project.path("node_modules").exists());
+    assert!(!project.read("package.json").contains("pkg"));
+}
+
+#[test]
+fn downloads_again_a_package_whose_cached_files_changed() {
+    let registry = Registry::start(vec![Package::new("pkg", "1.0.0")]);
+    let project = Project::new(&registry);
+    project.pie_ok(&["install", "pkg"]);
+
+    let cached_file = project.cache_dir().join("pkg@1.0.0/package/index.js");
+    std::fs::write(&cached_file, "tampered").unwrap();
+    registry.clear_requests();
+    let printed = project.pie_ok(&["install"]);
+
+    assert!(
+        printed.contains("downloading 'pkg@1.0.0' again"),
+        "{printed}"
+    );
+    assert!(registry
+        .requests()
+        .iter()
+        .any(|request| request.path == "-/pkg-1.0.0.tgz"));
+    assert_eq!(
+        std::fs::read_to_string(cached_file).unwrap(),
+        "module.exports = {};\n"
+    );
+}
+
+#[test]
+fn forces_overridden_versions_and_ignores_nested_overrides() {
+    let registry = Registry::start(vec![
+        Package::new("top", "1.0.0")
+            .dependency("dep", "^1.0.0")
+            .dependency("other", "^1.0.0"),
+        Package::new("dep", "1.0.0"),
+        Package::new("dep", "1.1.0"),
+        Package::new("other", "1.0.0").dependency("dep", "^1.0.0"),
+    ]);
+    let project = Project::new(&registry);
+    project.write(
+        "package.json",
+        r#"{
+            "name": "project",
+            "dependencies": { "top": "^1.0.0" },
+            "overrides": { "dep": "1.0.0", "other": { "dep": "1.1.0" } }
+        }"#,
+    );
+
+    let printed = project.pie_ok(&["install"]);
+
+    assert!(
+        printed.contains("ignoring the nested override of 'other'"),
+        "{printed}"
+    );
+    let installed = std::fs::read_to_string(project.path("node_modules/dep/package.json")).unwrap();
+    assert!(installed.contains("\"version\": \"1.0.0\""), "{installed}");
+    assert!(!project.cache_dir().join("dep@1.1.0").exists());
+}
+
+#[test]
+fn installs_the_workspaces_matching_a_glob() {
+    let registry = Registry::start(vec![Package::new("pkg", "1.0.0")]);
+    let project = Project::new(&registry);
+    project.write(
+        "package.json",
+        r#"{ "name": "monorepo", "workspaces": ["packages/*", "!packages/legacy"] }"#,
+    );
+    project.write(
+        "packages/app/package.json",
+        r#"{ "name": "app", "version": "1.0.0", "dependencies": { "lib": "^1.0.0", "pkg": "^1.0.0" } }"#,
+    );
+    project.write(
+        "packages/lib/package.json",
+        r#"{ "name": "lib", "version": "1.0.0" }"#,
+    );
+    project.write(
+        "packages/legacy/package.json",
+        r#"{ "name": "legacy", "version": "1.0.0" }"#,
+    );
+    project.write("packages/notes/README.md", "not a workspace");
+
+    project.pie_ok(&["install"]);
+
+    assert!(is_installed(&project.dir, "app"));
+    assert!(is_installed(&project.dir, "lib"));
+    assert!(is_installed(&project.dir, "pkg"));
+    assert!(!project.path("node_modules/legacy").exists());
+    assert!(!project.path("node_modules/notes").exists());
+    assert!(!registry
+        .requests()
+        .iter()
+        .any(|request| request.path == "lib"));
+}
+
+#[test]
+fn reuses_the_locked_versions_on_the_next_install() {
+    let registry = Registry::start(vec![
+        Package::new("first", "1.0.0").dependency("shared", "^1.0.0"),
+        Package::new("second", "1.0.0"),
+        Package::new("second", "2.0.0"),
+        Package::new("shared", "1.0.0"),
+    ]);
+    let project = Project::new(&registry);
+    project.write(
+        "package.json",
+        r#"{ "name": "project", "dependencies": { "first": "^1.0.0", "second": "^1.0.0" } }"#,
+    );
+    project.pie_ok(&["install"]);
+
+    registry.clear_requests();
+    project.pie_ok(&["install"]);
+    assert!(registry.requests().is_empty(), "{:?}", registry.requests());
+
+    // only the package whose range changed is resolved again
+    project.write(
+        "package.json",
+        r#"{ "name": "project", "dependencies": { "first": "^1.0.0", "second": "^2.0.0" } }"#,
+    );
+    project.pie_ok(&["install"]);
+    let resolved = registry
+        .requests()
+        .into_iter()
+        .map(|request| request.path)
+        .filter(|path| !path.starts_with("-/"))
+        .collect::<Vec<_>>();
+    assert_eq!(resolved, ["second"]);
+}
+
+#[test]
+fn resolves_again_a_locked_version_package_json_doesnt_allow() {
+    let registry = Registry::start(vec![
+        Package::new("other", "1.0.0"),
+        Package::new("pkg", "1.0.0"),
+        Package::new("pkg", "2.0.0"),
+    ]);
+    let project = Project::new(&registry);
+    project.pie_ok(&["install", "pkg@1.0.0"]);
+
+    // package.json moves on while 1.0.0 stays linked
+    project.write(
+        "package.json",
+        r#"{ "name": "project", "dependencies": { "pkg": "^2.0.0" } }"#,
+    );
+    project.pie_ok(&["install", "other"]);
+    assert!(project.read_json("pie-lock.json")["dependencies"]
+        .get("pkg")
+        .is_none());
+
+    let printed = common::failed(project.pie(&["install", "--frozen-lockfile"]));
+    assert!(
+        printed.contains("pie-lock.json has no version of 'pkg' matching '^2.0.0'"),
+        "{printed}"
+    );
+
+    project.pie_ok(&["install"]);
+    assert_eq!(
+        project.read_json("node_modules/pkg/package.json")["version"],
+        "2.0.0"
+    );
+    assert_eq!(
+        project.read_json("pie-lock.json")["dependencies"]["pkg"],
+        "2.0.0"
+    );
+}